@@ -0,0 +1,153 @@
+// アセンブルの結果
+
+use crate::parser::{CommandType, CommentSyntax};
+use crate::diagnostic::Diagnostic;
+use crate::isa::Isa;
+use crate::pseudo::Stack;
+use crate::symbol_table::SymbolTable;
+
+/// 変数シンボルにアドレスを割り当てる順番
+/// * `VariableOrder::FirstUse`は最初に参照された順（本のとおり）
+/// * `VariableOrder::Alphabetical`は名前の順
+/// * `VariableOrder::Frequency`は参照の多い順。同じ回数の場合は最初に参照された順
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum VariableOrder {
+    #[default]
+    FirstUse,
+    Alphabetical,
+    Frequency,
+}
+
+/// 変数シンボルの割り当ての設定
+/// * `base`は最初の変数のアドレス（既定は16）
+/// * `limit`は変数に使える最後のアドレス（既定はスクリーンの手前の16383）
+/// * `order`は割り当ての順番
+/// * `pinned`はアドレスを固定する変数とそのアドレス
+#[derive(Debug, PartialEq, Clone)]
+pub struct VariableOptions {
+    pub base: u16,
+    pub limit: u16,
+    pub order: VariableOrder,
+    pub pinned: Vec<(String, u16)>,
+}
+
+impl Default for VariableOptions {
+    fn default() -> VariableOptions {
+        VariableOptions {
+            base: 16,
+            limit: 0x3fff,
+            order: VariableOrder::FirstUse,
+            pinned: Vec::new(),
+        }
+    }
+}
+
+/// アセンブルの設定
+/// * `defines`はソースの外から定義するシンボルとその値
+/// * `no_predefined`は`SP`や`R0`などの定義済みシンボルを使わないかどうか
+/// * `comments`はコメントの書き方
+/// * `legacy_symbols`はシンボルの文法の検査をしないかどうか
+/// * `lint_comp`は本と異なるcompの書き方（`M+D`など）を警告するかどうか
+/// * `ignore_case`はモーニック（`d=m`、`0;jmp`など）の大文字と小文字を区別しないか
+/// * `ignore_symbol_case`はシンボルの大文字と小文字を区別しないか
+/// * `isa`は命令セット（ALUの拡張モードと有効にした拡張）
+/// * `stack`は`push`と`pop`のスタックの向き
+/// * `variables`は変数シンボルの割り当て方
+#[derive(Debug, PartialEq, Default)]
+pub struct AssembleOptions {
+    pub defines: Vec<(String, u16)>,
+    pub no_predefined: bool,
+    pub comments: CommentSyntax,
+    pub legacy_symbols: bool,
+    pub lint_comp: bool,
+    pub ignore_case: bool,
+    pub ignore_symbol_case: bool,
+    pub isa: Isa,
+    pub stack: Stack,
+    pub variables: VariableOptions,
+}
+
+/// アセンブルされた1つの命令
+/// * `line`はソース上の行番号（1から始まる）
+/// * `text`はコメントと空白を除いた元のコマンド
+/// * `address`は命令のROM上のアドレス
+/// * `word`は命令の機械語
+/// * `kind`は`CommandType::A`または`CommandType::C`
+/// * `symbol`はA命令がシンボルを参照している場合のシンボル名
+/// * `pseudo`は命令が疑似命令を展開したものである場合のもとの疑似命令
+#[derive(Debug, PartialEq)]
+pub struct Instruction {
+    pub line: usize,
+    pub text: String,
+    pub address: usize,
+    pub word: u16,
+    pub kind: CommandType,
+    pub symbol: Option<String>,
+    pub pseudo: Option<String>,
+}
+
+/// 1つのソースファイルをアセンブルした結果
+/// * `labels`はラベルシンボルとそのアドレスの定義順のリスト
+/// * `variables`は変数シンボルとそのアドレスの割り当て順のリスト
+/// * `symbols`は最終的なシンボルの表（種類、定義と参照の行を含む）
+/// * `warnings`はアセンブル中に見つかった警告
+#[derive(Debug, PartialEq)]
+pub struct Assembly {
+    pub file_name: String,
+    pub instructions: Vec<Instruction>,
+    pub labels: Vec<(String, usize)>,
+    pub variables: Vec<(String, usize)>,
+    pub symbols: SymbolTable,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Assembly {
+    /// 空の`Assembly`を作成する
+    pub fn new(file_name: &str) -> Assembly {
+        Assembly {
+            file_name: file_name.to_string(),
+            instructions: Vec::new(),
+            labels: Vec::new(),
+            variables: Vec::new(),
+            symbols: SymbolTable::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// 命令の機械語を順に並べたワード列を返す
+    pub fn words(&self) -> Vec<u16> {
+        self.instructions.iter().map(|i| i.word).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Assembly, Instruction};
+    use crate::parser::CommandType;
+
+    #[test]
+    fn test_assembly_words() {
+        let mut assembly = Assembly::new("Test.asm");
+        assert_eq!(assembly.words(), vec![]);
+
+        assembly.instructions.push(Instruction {
+            line: 1,
+            text: "@i".to_string(),
+            address: 0,
+            word: 16,
+            kind: CommandType::A,
+            symbol: Some("i".to_string()),
+            pseudo: None,
+        });
+        assembly.instructions.push(Instruction {
+            line: 2,
+            text: "D=M".to_string(),
+            address: 1,
+            word: 0xfc10,
+            kind: CommandType::C,
+            symbol: None,
+            pseudo: None,
+        });
+        assert_eq!(assembly.words(), vec![16, 0xfc10]);
+    }
+}
@@ -0,0 +1,668 @@
+// コマンドライン引数の解析
+
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::output::{Format, RomOptions};
+use crate::parser::CommentSyntax;
+use crate::isa::{Extension, Isa};
+use crate::pseudo::Stack;
+use crate::diagnostic::Diagnostic;
+use crate::assembly::{VariableOptions, VariableOrder};
+
+/// 正常終了
+pub const EXIT_OK: i32 = 0;
+/// コマンドラインの使い方が間違っている
+pub const EXIT_USAGE: i32 = 64;
+/// 入力のアセンブリ言語や機械語にエラーがある
+pub const EXIT_DATA: i32 = 65;
+/// 入力ファイルを読めない
+pub const EXIT_NO_INPUT: i32 = 66;
+/// 出力ファイルを書けない
+pub const EXIT_CANT_CREATE: i32 = 73;
+
+/// * `Subcommand::Assemble`はアセンブリ言語を機械語に変換する
+/// * `Subcommand::Disassemble`は`.hack`をアセンブリ言語に戻す
+/// * `Subcommand::Run`はプログラムをHackのCPUエミュレータで実行する
+/// * `Subcommand::Check`は出力せずにエラーと警告だけを表示する
+/// * `Subcommand::Fmt`はアセンブリ言語のソースを整形する
+/// * `Subcommand::Xref`はシンボルの相互参照表を表示する
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Subcommand {
+    Assemble,
+    Disassemble,
+    Run,
+    Check,
+    Fmt,
+    Xref,
+}
+
+impl Subcommand {
+    /// 名前からサブコマンドを返す
+    pub fn from_name(name: &str) -> Option<Subcommand> {
+        let subcommand = match name {
+            "assemble" => Subcommand::Assemble,
+            "disassemble" => Subcommand::Disassemble,
+            "run" => Subcommand::Run,
+            "check" => Subcommand::Check,
+            "fmt" => Subcommand::Fmt,
+            "xref" => Subcommand::Xref,
+            _ => return None
+        };
+
+        Some(subcommand)
+    }
+}
+
+/// 警告の扱い
+/// * `Warnings::On`は警告を表示する
+/// * `Warnings::Off`は警告を表示しない
+/// * `Warnings::Error`は警告をエラーとして扱う
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Warnings {
+    On,
+    Off,
+    Error,
+}
+
+/// 解析したコマンドライン引数
+/// * `inputs`は入力ファイルまたはディレクトリ。複数指定できるのは`assemble`と
+///   `check`だけ
+/// * `output`は出力ファイル名。`None`のときはサブコマンドごとの既定値になる
+/// * `out_dir`は複数の入力をアセンブルしたときの出力先のディレクトリ
+/// * `defines`は`-D NAME=VALUE`で定義されたシンボル
+/// * `symbol_files`はシンボルの定義ファイル。読み込んで`defines`に加える
+/// * `no_predefined`は`SP`や`R0`などの定義済みシンボルを使わないかどうか
+/// * `variables`は変数シンボルの割り当て方
+/// * `cycles`は`run`で実行する最大のサイクル数
+/// * `comments`はソースのコメントの書き方
+/// * `legacy_symbols`はシンボルの文法の検査をしないかどうか
+/// * `lint_comp`は本と異なるcompの書き方を警告するかどうか
+/// * `ignore_case`はモーニックの大文字と小文字を区別しないかどうか
+/// * `ignore_symbol_case`はシンボルの大文字と小文字を区別しないかどうか
+/// * `isa`は命令セット（`--extended-alu`と`--extension`で指定する）
+/// * `isa_file`は命令セットの対応表のファイル。読み込んで`isa`に設定する
+/// * `stack`は`push`と`pop`のスタックの向き
+/// * `watch`は入力の変更を監視して繰り返し実行するかどうか
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub subcommand: Subcommand,
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+    pub out_dir: Option<String>,
+    pub format: Option<Format>,
+    pub rom: RomOptions,
+    pub warnings: Warnings,
+    pub defines: Vec<(String, u16)>,
+    pub symbol_files: Vec<String>,
+    pub no_predefined: bool,
+    pub variables: VariableOptions,
+    pub cycles: usize,
+    pub comments: CommentSyntax,
+    pub legacy_symbols: bool,
+    pub lint_comp: bool,
+    pub ignore_case: bool,
+    pub ignore_symbol_case: bool,
+    pub isa: Isa,
+    pub isa_file: Option<String>,
+    pub stack: Stack,
+    pub watch: bool,
+}
+
+/// コマンドライン引数が要求する動作。`Cli`は一度しか作らないので大きさは気にしない
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Action {
+    Execute(Cli),
+    Help,
+    Version,
+}
+
+/// 使い方の説明を返す
+pub fn usage() -> String {
+    concat!(
+        "Usage: assembler [command] [options] <input>...\n",
+        "       assembler <input> [output]\n",
+        "\n",
+        "Commands:\n",
+        "  assemble     Assemble <input> files or directories (default)\n",
+        "  disassemble  Convert a .hack file back into assembly\n",
+        "  run          Run a .asm or .hack program on the Hack CPU emulator\n",
+        "  check        Report errors and warnings without writing output\n",
+        "  fmt          Reformat assembly source to stdout or the -o file\n",
+        "  xref         List symbols with their definitions and references\n",
+        "\n",
+        "Options:\n",
+        "  -o, --output <file>       Output file (`-` for stdout)\n",
+        "      --out-dir <dir>       Output directory for several inputs\n",
+        "  -f, --format <format>     hack, logisim, digital, coe, mif, json\n",
+        "                            or listing\n",
+        "      --depth <words>       ROM depth for coe and mif\n",
+        "      --fill <word>         Word for unused ROM in coe and mif\n",
+        "  -D, --define <NAME=VALUE> Define a symbol\n",
+        "      --symbols <file>      Define symbols from `NAME = VALUE` lines\n",
+        "      --no-predefined       Don't define SP, R0-R15, SCREEN, KBD, etc.\n",
+        "      --var-base <address>  First address for variables (default 16)\n",
+        "      --var-limit <address> Last address for variables (default 16383)\n",
+        "      --var-order <order>   first-use (default), alphabetical or frequency\n",
+        "      --pin <NAME=ADDRESS>  Put a variable at a fixed address\n",
+        "  -W, --warnings <mode>     on, off or error\n",
+        "      --cycles <n>          Maximum cycles for run (default 100000)\n",
+        "      --watch               Run again whenever an input changes\n",
+        "      --semicolon-comments  Treat `;` not followed by a jump as a comment\n",
+        "      --no-block-comments   Don't treat `/* ... */` as a comment\n",
+        "      --legacy-symbols      Accept any text as a symbol name\n",
+        "      --lint-comp           Warn about comp spellings like `M+D`\n",
+        "      --ignore-case         Accept mnemonics in any case (`d=m`, `0;jmp`)\n",
+        "      --ignore-symbol-case  Treat symbols differing only in case as the same\n",
+        "      --extended-alu        Accept all 64 ALU functions and `%ALU(...)`\n",
+        "      --extension <name>    Enable an instruction set extension (shift)\n",
+        "      --isa <file>          Load dest, comp and jump tables from TOML or JSON\n",
+        "                            (not with run)\n",
+        "      --stack <direction>   Grow the push/pop stack up (default) or down\n",
+        "  -h, --help                Print this help\n",
+        "  -V, --version             Print the version\n",
+        "\n",
+        "`-` as <input> reads from stdin.\n",
+    ).to_string()
+}
+
+/// `0x`で始まる16進数または10進数の文字列を16bitのワードに変換する
+pub fn parse_word(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => u16::from_str(s).ok(),
+    }
+}
+
+/// `NAME=VALUE`を解析する。値はA命令で読み込める15bitの範囲に限る
+fn parse_define(s: &str) -> Option<(String, u16)> {
+    let (name, value) = s.split_once('=')?;
+    let value = parse_word(value)?;
+    if name.is_empty() || value > 0x7fff {
+        return None
+    }
+
+    Some((name.to_string(), value))
+}
+
+/// `NAME = VALUE`を1行に1つずつ書いたシンボルの定義ファイルを解析する。
+/// `#`から行末まではコメント
+pub fn parse_symbol_file(file_name: &str, text: &str)
+    -> Result<Vec<(String, u16)>, Vec<Diagnostic>> {
+    let mut symbols = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let define: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+        match parse_define(&define) {
+            Some(symbol) => symbols.push(symbol),
+            None => errors.push(Diagnostic::error(file_name, Some(i + 1),
+                format!("`{}` is not a valid NAME = VALUE", line))),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+
+    Ok(symbols)
+}
+
+/// コマンドライン引数（プログラム名を除く）を解析する。間違っている場合は
+/// エラーメッセージを返す
+pub fn parse(args: &[String]) -> Result<Action, String> {
+    let mut args = args.iter().peekable();
+    let mut cli = Cli {
+        subcommand: Subcommand::Assemble,
+        inputs: Vec::new(),
+        output: None,
+        out_dir: None,
+        format: None,
+        rom: RomOptions::default(),
+        warnings: Warnings::On,
+        defines: Vec::new(),
+        symbol_files: Vec::new(),
+        no_predefined: false,
+        variables: VariableOptions::default(),
+        cycles: 100000,
+        comments: CommentSyntax::default(),
+        legacy_symbols: false,
+        lint_comp: false,
+        ignore_case: false,
+        ignore_symbol_case: false,
+        isa: Isa::default(),
+        isa_file: None,
+        stack: Stack::Up,
+        watch: false,
+    };
+
+    // サブコマンドを省略した場合は`<input> [output]`として扱う
+    let legacy = match args.peek().and_then(|arg| Subcommand::from_name(arg)) {
+        Some(subcommand) => {
+            cli.subcommand = subcommand;
+            args.next();
+            false
+        },
+        None => true
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "-" || !arg.starts_with('-') {
+            cli.inputs.push(arg.to_string());
+            continue;
+        }
+
+        // `--name=value`、`--name value`、`-Xvalue`、`-X value`を受け付ける
+        let (name, inline) = if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (long.to_string(), None),
+            }
+        } else {
+            let (name, value) = arg[1..].split_at(arg[1..].chars().next().unwrap().len_utf8());
+            let long = match name {
+                "o" => "output",
+                "f" => "format",
+                "D" => "define",
+                "W" => "warnings",
+                "h" => "help",
+                "V" => "version",
+                _ => return Err(format!("unknown option `{}`", arg)),
+            };
+            let value = if value.is_empty() { None } else { Some(value.to_string()) };
+            (long.to_string(), value)
+        };
+
+        match name.as_str() {
+            "help" => return Ok(Action::Help),
+            "version" => return Ok(Action::Version),
+            "watch" => {
+                cli.watch = true;
+                continue;
+            },
+            "semicolon-comments" => {
+                cli.comments.semicolon = true;
+                continue;
+            },
+            "no-block-comments" => {
+                cli.comments.block = false;
+                continue;
+            },
+            "legacy-symbols" => {
+                cli.legacy_symbols = true;
+                continue;
+            },
+            "no-predefined" => {
+                cli.no_predefined = true;
+                continue;
+            },
+            "lint-comp" => {
+                cli.lint_comp = true;
+                continue;
+            },
+            "ignore-case" => {
+                cli.ignore_case = true;
+                continue;
+            },
+            "ignore-symbol-case" => {
+                cli.ignore_symbol_case = true;
+                continue;
+            },
+            "extended-alu" => {
+                cli.isa.extended_alu = true;
+                continue;
+            },
+            _ => ()
+        }
+
+        let value = match inline.or_else(|| args.next().cloned()) {
+            Some(value) => value,
+            None => return Err(format!("`{}` requires a value", arg)),
+        };
+
+        match name.as_str() {
+            "output" => cli.output = Some(value),
+            "out-dir" => cli.out_dir = Some(value),
+            "format" => {
+                cli.format = match Format::from_name(&value) {
+                    Some(format) => Some(format),
+                    None => return Err(format!("{} is not a supported format", value)),
+                };
+            },
+            "depth" => {
+                cli.rom.depth = match usize::from_str(&value) {
+                    Ok(depth) if depth > 0 => Some(depth),
+                    _ => return Err(format!("{} is not a valid ROM depth", value)),
+                };
+            },
+            "fill" => {
+                cli.rom.fill = match parse_word(&value) {
+                    Some(fill) => Some(fill),
+                    None => return Err(format!("{} is not a valid fill word", value)),
+                };
+            },
+            "define" => {
+                match parse_define(&value) {
+                    Some(define) => cli.defines.push(define),
+                    None => return Err(format!("{} is not a valid NAME=VALUE", value)),
+                }
+            },
+            "symbols" => cli.symbol_files.push(value),
+            "var-base" | "var-limit" => {
+                let address = match parse_word(&value) {
+                    Some(address) if address <= 0x7fff => address,
+                    _ => return Err(format!("{} is not a valid address", value)),
+                };
+                if name == "var-base" {
+                    cli.variables.base = address;
+                } else {
+                    cli.variables.limit = address;
+                }
+            },
+            "var-order" => {
+                cli.variables.order = match value.as_str() {
+                    "first-use" => VariableOrder::FirstUse,
+                    "alphabetical" => VariableOrder::Alphabetical,
+                    "frequency" => VariableOrder::Frequency,
+                    _ => return Err(format!("{} is not a valid variable order", value)),
+                };
+            },
+            "pin" => {
+                match parse_define(&value) {
+                    Some(pin) => cli.variables.pinned.push(pin),
+                    None => return Err(format!("{} is not a valid NAME=ADDRESS", value)),
+                }
+            },
+            "warnings" => {
+                cli.warnings = match value.as_str() {
+                    "on" => Warnings::On,
+                    "off" => Warnings::Off,
+                    "error" => Warnings::Error,
+                    _ => return Err(format!("{} is not a valid warning mode", value)),
+                };
+            },
+            "extension" => {
+                match Extension::builtin(&value) {
+                    Some(extension) => cli.isa.extensions.push(extension),
+                    None => return Err(format!("{} is not a known extension", value)),
+                }
+            },
+            "isa" => cli.isa_file = Some(value),
+            "stack" => {
+                cli.stack = match value.as_str() {
+                    "up" => Stack::Up,
+                    "down" => Stack::Down,
+                    _ => return Err(format!("{} is not a valid stack direction", value)),
+                };
+            },
+            "cycles" => {
+                cli.cycles = match usize::from_str(&value) {
+                    Ok(cycles) => cycles,
+                    Err(_) => return Err(format!("{} is not a valid cycle count", value)),
+                };
+            },
+            _ => return Err(format!("unknown option `--{}`", name)),
+        }
+    }
+
+    // `<input> <output>`の形式。ただし`.asm`は入力として扱い、上書きしない
+    if legacy && cli.inputs.len() == 2 && cli.output.is_none()
+       && !cli.inputs[1].ends_with(".asm") {
+        cli.output = cli.inputs.pop();
+    }
+
+    // 結果を表示するだけのサブコマンドは出力ディレクトリを使わない
+    if cli.out_dir.is_some()
+       && matches!(cli.subcommand, Subcommand::Run | Subcommand::Check | Subcommand::Xref) {
+        return Err("--out-dir can't be used with run, check or xref".to_string())
+    }
+
+    // エミュレータは本の対応表の機械語しか実行できない
+    if cli.isa_file.is_some() && cli.subcommand == Subcommand::Run {
+        return Err("--isa can't be used with run".to_string())
+    }
+
+    if cli.rom.is_set() && (cli.subcommand != Subcommand::Assemble
+                            || cli.format.is_some_and(|format| !format.is_rom())) {
+        return Err("--depth and --fill can only be used with coe and mif".to_string())
+    }
+
+    // ディレクトリを展開して複数のファイルを処理できるのはassembleとcheckだけ
+    let batch = matches!(cli.subcommand, Subcommand::Assemble | Subcommand::Check);
+    if let Some(dir) = cli.inputs.iter().find(|input| *input != "-" && Path::new(input).is_dir()) {
+        if !batch {
+            return Err(format!("`{}` is a directory; only assemble and check accept directories",
+                               dir))
+        }
+    }
+
+    match cli.inputs.len() {
+        0 => Err("no input file".to_string()),
+        1 => Ok(Action::Execute(cli)),
+        _ if batch => Ok(Action::Execute(cli)),
+        _ => Err(format!("unexpected argument `{}`", cli.inputs[1])),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, parse_define, parse_symbol_file, parse_word};
+    use super::{Action, Cli, Subcommand, Warnings};
+    use crate::diagnostic::Diagnostic;
+    use crate::assembly::{VariableOptions, VariableOrder};
+    use crate::output::{Format, RomOptions};
+    use crate::parser::CommentSyntax;
+    use crate::isa::{Extension, Isa};
+    use crate::pseudo::Stack;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    fn cli(subcommand: Subcommand, input: &str) -> Cli {
+        Cli {
+            subcommand,
+            inputs: vec![input.to_string()],
+            output: None,
+            out_dir: None,
+            format: None,
+            rom: RomOptions::default(),
+            warnings: Warnings::On,
+            defines: Vec::new(),
+            symbol_files: Vec::new(),
+            no_predefined: false,
+            variables: VariableOptions::default(),
+            cycles: 100000,
+            comments: CommentSyntax::default(),
+            legacy_symbols: false,
+            lint_comp: false,
+            ignore_case: false,
+            ignore_symbol_case: false,
+            isa: Isa::default(),
+            isa_file: None,
+            stack: Stack::Up,
+            watch: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_word() {
+        assert_eq!(parse_word("0"), Some(0));
+        assert_eq!(parse_word("65535"), Some(0xffff));
+        assert_eq!(parse_word("0xEA87"), Some(0xea87));
+        assert_eq!(parse_word("65536"), None);
+        assert_eq!(parse_word("zz"), None);
+    }
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(parse_define("TIMER=0x6001"), Some(("TIMER".to_string(), 0x6001)));
+        assert_eq!(parse_define("N=10"), Some(("N".to_string(), 10)));
+        assert_eq!(parse_define("N=0x8000"), None);
+        assert_eq!(parse_define("=1"), None);
+        assert_eq!(parse_define("N"), None);
+    }
+
+    #[test]
+    fn test_parse_symbol_file() {
+        let text = concat!("# 拡張したHackコンピュータ\n",
+                           "TIMER = 0x6001\n",
+                           "\n",
+                           "SERIAL=0x6002  # 送受信\n");
+        assert_eq!(parse_symbol_file("hack.sym", text),
+                   Ok(vec![("TIMER".to_string(), 0x6001), ("SERIAL".to_string(), 0x6002)]));
+
+        assert_eq!(parse_symbol_file("hack.sym", "TIMER\nLED = 0x8000\n"), Err(vec![
+            Diagnostic::error("hack.sym", Some(1),
+                "`TIMER` is not a valid NAME = VALUE".to_string()),
+            Diagnostic::error("hack.sym", Some(2),
+                "`LED = 0x8000` is not a valid NAME = VALUE".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        let mut expected = cli(Subcommand::Assemble, "Add.asm");
+        assert_eq!(parse(&args("Add.asm")), Ok(Action::Execute(expected)));
+
+        expected = cli(Subcommand::Assemble, "Add.asm");
+        expected.output = Some("Add.hex".to_string());
+        expected.format = Some(Format::Digital);
+        assert_eq!(parse(&args("Add.asm Add.hex --format=digital")),
+                   Ok(Action::Execute(expected)));
+    }
+
+    #[test]
+    fn test_parse_subcommand() {
+        let mut expected = cli(Subcommand::Assemble, "-");
+        expected.output = Some("-".to_string());
+        expected.format = Some(Format::Mif);
+        expected.rom = RomOptions { depth: Some(1024), fill: Some(0xffff) };
+        expected.warnings = Warnings::Error;
+        expected.defines = vec![("A".to_string(), 1), ("B".to_string(), 2)];
+        assert_eq!(parse(&args("assemble - -o - -f mif --depth 1024 --fill=0xffff \
+                                -W error -D A=1 -DB=2")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Run, "Add.hack");
+        expected.cycles = 10;
+        assert_eq!(parse(&args("run Add.hack --cycles 10")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "Add.asm");
+        expected.watch = true;
+        assert_eq!(parse(&args("check --watch Add.asm")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "Add.asm");
+        expected.comments = CommentSyntax { block: false, semicolon: true };
+        assert_eq!(parse(&args("check --semicolon-comments --no-block-comments Add.asm")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "Add.asm");
+        expected.legacy_symbols = true;
+        expected.lint_comp = true;
+        assert_eq!(parse(&args("check --legacy-symbols --lint-comp Add.asm")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "Add.asm");
+        expected.ignore_case = true;
+        expected.ignore_symbol_case = true;
+        assert_eq!(parse(&args("check --ignore-case --ignore-symbol-case Add.asm")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "Add.asm");
+        expected.variables = VariableOptions {
+            base: 0x100,
+            limit: 0x1ff,
+            order: VariableOrder::Frequency,
+            pinned: vec![("i".to_string(), 0x10)],
+        };
+        assert_eq!(parse(&args("check --var-base 0x100 --var-limit=511 \
+                                --var-order frequency --pin i=16 Add.asm")),
+                   Ok(Action::Execute(expected)));
+        assert!(parse(&args("check --var-order random Add.asm")).is_err());
+        assert!(parse(&args("check --var-base 0x8000 Add.asm")).is_err());
+
+        let expected = cli(Subcommand::Disassemble, "Add.hack");
+        assert_eq!(parse(&args("disassemble Add.hack")),
+                   Ok(Action::Execute(expected)));
+
+        let expected = cli(Subcommand::Xref, "Prog.asm");
+        assert_eq!(parse(&args("xref Prog.asm")), Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Disassemble, "Add.hack");
+        expected.isa.extended_alu = true;
+        expected.isa.extensions.push(Extension::builtin("shift").unwrap());
+        assert_eq!(parse(&args("disassemble --extended-alu --extension shift Add.hack")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Assemble, "Add.asm");
+        expected.isa_file = Some("hack2.toml".to_string());
+        expected.stack = Stack::Down;
+        expected.symbol_files = vec!["hack.sym".to_string()];
+        expected.no_predefined = true;
+        assert_eq!(parse(&args("Add.asm --isa hack2.toml --stack down --symbols hack.sym \
+                                --no-predefined")),
+                   Ok(Action::Execute(expected)));
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let mut expected = cli(Subcommand::Assemble, "a.asm");
+        expected.inputs.push("b.asm".to_string());
+        assert_eq!(parse(&args("a.asm b.asm")), Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Assemble, "a.asm");
+        expected.inputs.push("dir".to_string());
+        expected.inputs.push("c.asm".to_string());
+        expected.out_dir = Some("out".to_string());
+        assert_eq!(parse(&args("assemble a.asm dir c.asm --out-dir out")),
+                   Ok(Action::Execute(expected)));
+
+        let mut expected = cli(Subcommand::Check, "a.asm");
+        expected.inputs.push("b.asm".to_string());
+        assert_eq!(parse(&args("check a.asm b.asm")), Ok(Action::Execute(expected)));
+        assert!(parse(&args("check a.asm --out-dir out")).is_err());
+        assert!(parse(&args("run Add.asm --out-dir out")).is_err());
+
+        // ディレクトリはassembleとcheckだけが受け付ける
+        assert!(parse(&args("check src")).is_ok());
+        for subcommand in ["run", "fmt", "xref", "disassemble"] {
+            assert_eq!(parse(&args(&format!("{} src", subcommand))),
+                       Err("`src` is a directory; only assemble and check accept directories"
+                           .to_string()));
+        }
+    }
+
+    #[test]
+    fn test_parse_help_and_version() {
+        assert_eq!(parse(&args("--help")), Ok(Action::Help));
+        assert_eq!(parse(&args("check -h")), Ok(Action::Help));
+        assert_eq!(parse(&args("-V")), Ok(Action::Version));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(parse(&args("")).is_err());
+        assert!(parse(&args("check")).is_err());
+        assert!(parse(&args("run a.asm b.asm")).is_err());
+        assert!(parse(&args("a.asm --format")).is_err());
+        assert!(parse(&args("a.asm --format=zip")).is_err());
+        assert!(parse(&args("a.asm -x")).is_err());
+        assert!(parse(&args("a.asm --nothing=1")).is_err());
+        assert!(parse(&args("a.asm -W loud")).is_err());
+        assert!(parse(&args("a.asm -f coe --depth 0")).is_err());
+        assert_eq!(parse(&args("a.asm -f json --depth 4")),
+                   Err("--depth and --fill can only be used with coe and mif".to_string()));
+        assert!(parse(&args("run a.asm --fill 0xffff")).is_err());
+        assert_eq!(parse(&args("run a.asm --isa cpu.toml")),
+                   Err("--isa can't be used with run".to_string()));
+    }
+}
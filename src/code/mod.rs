@@ -7,78 +7,220 @@
 /// ```
 pub struct Code {}
 
+/// destモーニックとバイナリコードの対応表
+const DEST: [(&str, &str); 8] = [
+    ("",    "000"),
+    ("M",   "001"),
+    ("D",   "010"),
+    ("MD",  "011"),
+    ("A",   "100"),
+    ("AM",  "101"),
+    ("AD",  "110"),
+    ("AMD", "111"),
+];
+
+/// compモーニックとバイナリコード（a + c1〜c6）の対応表
+const COMP: [(&str, &str); 28] = [
+    // a=0のとき
+    ("0",   concat!("0", "101010")),
+    ("1",   concat!("0", "111111")),
+    ("-1",  concat!("0", "111010")),
+    ("D",   concat!("0", "001100")),
+    ("A",   concat!("0", "110000")),
+    ("!D",  concat!("0", "001101")),
+    ("!A",  concat!("0", "110001")),
+    ("-D",  concat!("0", "001111")),
+    ("-A",  concat!("0", "110011")),
+    ("D+1", concat!("0", "011111")),
+    ("A+1", concat!("0", "110111")),
+    ("D-1", concat!("0", "001110")),
+    ("A-1", concat!("0", "110010")),
+    ("D+A", concat!("0", "000010")),
+    ("D-A", concat!("0", "010011")),
+    ("A-D", concat!("0", "000111")),
+    ("D&A", concat!("0", "000000")),
+    ("D|A", concat!("0", "010101")),
+    // a=1のとき
+    ("M",   concat!("1", "110000")),
+    ("!M",  concat!("1", "110001")),
+    ("-M",  concat!("1", "110011")),
+    ("M+1", concat!("1", "110111")),
+    ("M-1", concat!("1", "110010")),
+    ("D+M", concat!("1", "000010")),
+    ("D-M", concat!("1", "010011")),
+    ("M-D", concat!("1", "000111")),
+    ("D&M", concat!("1", "000000")),
+    ("D|M", concat!("1", "010101")),
+];
+
+/// 拡張モードで使える、本に載っていないcompモーニックとバイナリコードの対応表。
+/// `-D-1`などは本のcompの別名
+const EXTRA_COMP: [(&str, &str); 29] = [
+    // a=0のとき
+    ("!(D&A)", concat!("0", "000001")),
+    ("-D-A-1", concat!("0", "000011")),
+    ("D&!A",   concat!("0", "000100")),
+    ("!D|A",   concat!("0", "000101")),
+    ("D-A-1",  concat!("0", "000110")),
+    ("!D&A",   concat!("0", "010000")),
+    ("D|!A",   concat!("0", "010001")),
+    ("A-D-1",  concat!("0", "010010")),
+    ("!(D|A)", concat!("0", "010100")),
+    ("-D-A-2", concat!("0", "010110")),
+    ("D+A+1",  concat!("0", "010111")),
+    ("-D-2",   concat!("0", "011110")),
+    ("-A-2",   concat!("0", "110110")),
+    ("-2",     concat!("0", "111110")),
+    ("-D-1",   concat!("0", "001101")),
+    ("-A-1",   concat!("0", "110001")),
+    // a=1のとき
+    ("!(D&M)", concat!("1", "000001")),
+    ("-D-M-1", concat!("1", "000011")),
+    ("D&!M",   concat!("1", "000100")),
+    ("!D|M",   concat!("1", "000101")),
+    ("D-M-1",  concat!("1", "000110")),
+    ("!D&M",   concat!("1", "010000")),
+    ("D|!M",   concat!("1", "010001")),
+    ("M-D-1",  concat!("1", "010010")),
+    ("!(D|M)", concat!("1", "010100")),
+    ("-D-M-2", concat!("1", "010110")),
+    ("D+M+1",  concat!("1", "010111")),
+    ("-M-2",   concat!("1", "110110")),
+    ("-M-1",   concat!("1", "110001")),
+];
+
+/// jumpモーニックとバイナリコードの対応表
+const JUMP: [(&str, &str); 8] = [
+    ("",    "000"),
+    ("JGT", "001"),
+    ("JEQ", "010"),
+    ("JGE", "011"),
+    ("JLT", "100"),
+    ("JNE", "101"),
+    ("JLE", "110"),
+    ("JMP", "111"),
+];
+
+/// 対応表からモーニックのバイナリコードを探す
+fn to_code(table: &[(&str, &str)], monic: &str) -> Option<String> {
+    table.iter()
+        .find(|(m, _)| *m == monic)
+        .map(|(_, code)| code.to_string())
+}
+
+/// 対応表からバイナリコードのモーニックを探す
+fn to_monic(table: &[(&str, &str)], code: &str) -> Option<String> {
+    table.iter()
+        .find(|(_, c)| *c == code)
+        .map(|(monic, _)| monic.to_string())
+}
+
 impl Code {
-    /// destモーニックのバイナリコードを返す
+    /// destモーニックのバイナリコードを返す。レジスタの順番は問わない
+    /// （`DM`、`ADM`など）が、同じレジスタを2回書くことはできない
     pub fn dest(monic: &str) -> Option<String> {
-        let code = match monic {
-            "" => "000",
-            "M" => "001",
-            "D" => "010",
-            "MD" => "011",
-            "A" => "100",
-            "AM" => "101",
-            "AD" => "110",
-            "AMD" => "111",
-            _ => return None
-        };
+        to_code(&DEST, &Code::canonical_dest(monic)?)
+    }
 
-        Some(code.to_string())
+    /// destモーニックのレジスタを本の順番（A、M、D）に並べ替えて返す。
+    /// A、M、D以外の文字や重複したレジスタがある場合は`None`を返す
+    pub fn canonical_dest(monic: &str) -> Option<String> {
+        let mut canonical = String::new();
+        for register in ['A', 'M', 'D'] {
+            match monic.matches(register).count() {
+                0 => (),
+                1 => canonical.push(register),
+                _ => return None
+            }
+        }
+
+        if canonical.len() != monic.len() {
+            return None
+        }
+
+        Some(canonical)
     }
 
-    /// compモーニックのバイナリコードを返す
+    /// compモーニックのバイナリコードを返す。`M+D`のように可換な演算の左右を
+    /// 入れ替えた書き方も受け付ける
     pub fn comp(monic: &str) -> Option<String> {
-        let code = match monic {
-            // a=0のとき
-            "0"   => concat!("0", "101010"),
-            "1"   => concat!("0", "111111"),
-            "-1"  => concat!("0", "111010"),
-            "D"   => concat!("0", "001100"),
-            "A"   => concat!("0", "110000"),
-            "!D"  => concat!("0", "001101"),
-            "!A"  => concat!("0", "110001"),
-            "-D"  => concat!("0", "001111"),
-            "-A"  => concat!("0", "110011"),
-            "D+1" => concat!("0", "011111"),
-            "A+1" => concat!("0", "110111"),
-            "D-1" => concat!("0", "001110"),
-            "A-1" => concat!("0", "110010"),
-            "D+A" => concat!("0", "000010"),
-            "D-A" => concat!("0", "010011"),
-            "A-D" => concat!("0", "000111"),
-            "D&A" => concat!("0", "000000"),
-            "D|A" => concat!("0", "010101"),
-            // a=1のとき
-            "M"   => concat!("1", "110000"),
-            "!M"  => concat!("1", "110001"),
-            "-M"  => concat!("1", "110011"),
-            "M+1" => concat!("1", "110111"),
-            "M-1" => concat!("1", "110010"),
-            "D+M" => concat!("1", "000010"),
-            "D-M" => concat!("1", "010011"),
-            "M-D" => concat!("1", "000111"),
-            "D&M" => concat!("1", "000000"),
-            "D|M" => concat!("1", "010101"),
-            _ => return None
+        to_code(&COMP, &Code::canonical_comp(monic)?)
+    }
+
+    /// compモーニックを本の書き方に直す。可換な演算子（`+`、`&`、`|`）の左右を
+    /// 入れ替えた書き方（`M+D`、`1+D`、`A|D`など）は入れ替えて返す
+    pub fn canonical_comp(monic: &str) -> Option<String> {
+        if to_code(&COMP, monic).is_some() {
+            return Some(monic.to_string())
+        }
+
+        Code::commuted(monic).into_iter().find(|swapped| to_code(&COMP, swapped).is_some())
+    }
+
+    /// 可換な演算子（`+`、`&`、`|`）の左右を入れ替えたcompモーニックの候補を返す
+    pub fn commuted(monic: &str) -> Vec<String> {
+        ['+', '&', '|'].iter()
+            .filter_map(|op| monic.split_once(*op)
+                .map(|(left, right)| format!("{}{}{}", right, op, left)))
+            .collect()
+    }
+
+    /// 拡張モードでcompモーニックのバイナリコードを返す。本のcompに加えて、
+    /// 本に載っていない組み合わせの名前（`-D-1`、`D+A+1`など）と、ALUの制御
+    /// ビットを直接書く`%ALU(zx,nx,zy,ny,f,no)`（yはA）、
+    /// `%ALUM(zx,nx,zy,ny,f,no)`（yはM）を受け付ける
+    pub fn comp_extended(monic: &str) -> Option<String> {
+        if let Some(code) = Code::comp(monic).or_else(|| to_code(&EXTRA_COMP, monic)) {
+            return Some(code)
+        }
+
+        let (a, args) = if let Some(args) = monic.strip_prefix("%ALUM(") {
+            ("1", args)
+        } else {
+            ("0", monic.strip_prefix("%ALU(")?)
         };
+        let bits: Vec<&str> = args.strip_suffix(')')?.split(',').collect();
+        if bits.len() != 6 || bits.iter().any(|b| *b != "0" && *b != "1") {
+            return None
+        }
 
-        return Some(code.to_string())
+        Some(format!("{}{}", a, bits.concat()))
     }
 
     /// jumpモーニックのバイナリコードを返す
     pub fn jump(monic: &str) -> Option<String> {
-        let code = match monic {
-            "" => "000",
-            "JGT" => "001",
-            "JEQ" => "010",
-            "JGE" => "011",
-            "JLT" => "100",
-            "JNE" => "101",
-            "JLE" => "110",
-            "JMP" => "111",
-            _ => return None
-        };
+        to_code(&JUMP, monic)
+    }
+
+    /// destのバイナリコードのモーニックを返す
+    pub fn dest_monic(code: &str) -> Option<String> {
+        to_monic(&DEST, code)
+    }
+
+    /// compのバイナリコード（a + c1〜c6）のモーニックを返す
+    pub fn comp_monic(code: &str) -> Option<String> {
+        to_monic(&COMP, code)
+    }
 
-        Some(code.to_string())
+    /// 拡張モードでcompのバイナリコードのモーニックを返す。本のcompでも名前の
+    /// ある組み合わせでもない場合は`%ALU(...)`または`%ALUM(...)`の形にする
+    pub fn comp_monic_extended(code: &str) -> Option<String> {
+        if let Some(monic) = Code::comp_monic(code).or_else(|| to_monic(&EXTRA_COMP, code)) {
+            return Some(monic)
+        }
+
+        if code.len() != 7 || !code.chars().all(|c| c == '0' || c == '1') {
+            return None
+        }
+        let name = if &code[..1] == "1" { "%ALUM" } else { "%ALU" };
+        let bits: Vec<String> = code[1..].chars().map(|c| c.to_string()).collect();
+
+        Some(format!("{}({})", name, bits.join(",")))
+    }
+
+    /// jumpのバイナリコードのモーニックを返す
+    pub fn jump_monic(code: &str) -> Option<String> {
+        to_monic(&JUMP, code)
     }
 }
 
@@ -99,6 +241,26 @@ mod test {
         assert_eq!(Code::dest("a"), None);
     }
 
+    #[test]
+    fn test_code_dest_permutations() {
+        assert_eq!(&Code::dest("DM").unwrap(), "011");
+        assert_eq!(&Code::dest("MA").unwrap(), "101");
+        assert_eq!(&Code::dest("DA").unwrap(), "110");
+        assert_eq!(&Code::dest("DAM").unwrap(), "111");
+        assert_eq!(&Code::dest("MDA").unwrap(), "111");
+        assert_eq!(Code::dest("MM"), None);
+        assert_eq!(Code::dest("AMDA"), None);
+        assert_eq!(Code::dest("MX"), None);
+    }
+
+    #[test]
+    fn test_code_canonical_dest() {
+        assert_eq!(&Code::canonical_dest("").unwrap(), "");
+        assert_eq!(&Code::canonical_dest("DM").unwrap(), "MD");
+        assert_eq!(&Code::canonical_dest("ADM").unwrap(), "AMD");
+        assert_eq!(Code::canonical_dest("MM"), None);
+    }
+
     #[test]
     fn test_code_jump() {
         assert_eq!(&Code::jump(""   ).unwrap(), "000");
@@ -111,4 +273,70 @@ mod test {
         assert_eq!(&Code::jump("JMP").unwrap(), "111");
         assert_eq!(Code::jump("a"), None);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_code_comp() {
+        assert_eq!(&Code::comp("0").unwrap(), "0101010");
+        assert_eq!(&Code::comp("D+A").unwrap(), "0000010");
+        assert_eq!(&Code::comp("D|M").unwrap(), "1010101");
+        assert_eq!(&Code::comp("A+D").unwrap(), "0000010");
+        assert_eq!(&Code::comp("M&D").unwrap(), "1000000");
+        assert_eq!(Code::comp("A-D+"), None);
+        assert_eq!(Code::comp("1-D"), None);
+    }
+
+    #[test]
+    fn test_code_canonical_comp() {
+        assert_eq!(&Code::canonical_comp("D+M").unwrap(), "D+M");
+        assert_eq!(&Code::canonical_comp("M+D").unwrap(), "D+M");
+        assert_eq!(&Code::canonical_comp("A+D").unwrap(), "D+A");
+        assert_eq!(&Code::canonical_comp("1+D").unwrap(), "D+1");
+        assert_eq!(&Code::canonical_comp("1+M").unwrap(), "M+1");
+        assert_eq!(&Code::canonical_comp("M&D").unwrap(), "D&M");
+        assert_eq!(&Code::canonical_comp("A|D").unwrap(), "D|A");
+        assert_eq!(Code::canonical_comp("A-D+"), None);
+        assert_eq!(Code::canonical_comp("D-M+"), None);
+        assert_eq!(Code::canonical_comp("+"), None);
+    }
+
+    #[test]
+    fn test_code_monic() {
+        assert_eq!(&Code::dest_monic("011").unwrap(), "MD");
+        assert_eq!(&Code::comp_monic("1110000").unwrap(), "M");
+        assert_eq!(&Code::jump_monic("111").unwrap(), "JMP");
+        assert_eq!(&Code::jump_monic("000").unwrap(), "");
+        assert_eq!(Code::comp_monic("0000001"), None);
+    }
+
+    #[test]
+    fn test_code_comp_extended() {
+        assert_eq!(&Code::comp_extended("D+A").unwrap(), "0000010");
+        assert_eq!(&Code::comp_extended("M+D").unwrap(), "1000010");
+        assert_eq!(&Code::comp_extended("-D-1").unwrap(), "0001101");
+        assert_eq!(&Code::comp_extended("D+M+1").unwrap(), "1010111");
+        assert_eq!(&Code::comp_extended("-2").unwrap(), "0111110");
+        assert_eq!(&Code::comp_extended("%ALU(0,1,0,1,1,1)").unwrap(), "0010111");
+        assert_eq!(&Code::comp_extended("%ALUM(1,1,0,0,0,0)").unwrap(), "1110000");
+        assert_eq!(Code::comp_extended("%ALU(0,1,0,1,1)"), None);
+        assert_eq!(Code::comp_extended("%ALU(0,1,0,1,1,2)"), None);
+        assert_eq!(Code::comp_extended("%ALU(0,1,0,1,1,1"), None);
+        assert_eq!(Code::comp("-D-1"), None);
+        assert_eq!(Code::comp("%ALU(0,0,0,0,0,0)"), None);
+    }
+
+    #[test]
+    fn test_code_comp_monic_extended() {
+        assert_eq!(&Code::comp_monic_extended("0000010").unwrap(), "D+A");
+        assert_eq!(&Code::comp_monic_extended("0001101").unwrap(), "!D");
+        assert_eq!(&Code::comp_monic_extended("1010111").unwrap(), "D+M+1");
+        assert_eq!(&Code::comp_monic_extended("0100000").unwrap(), "%ALU(1,0,0,0,0,0)");
+        assert_eq!(&Code::comp_monic_extended("1111111").unwrap(), "%ALUM(1,1,1,1,1,1)");
+        assert_eq!(Code::comp_monic_extended("011111"), None);
+
+        // aビットとALUの制御ビットのすべての組み合わせが往復できる
+        for bits in 0..128 {
+            let code = format!("{:07b}", bits);
+            let monic = Code::comp_monic_extended(&code).unwrap();
+            assert_eq!(Code::comp_extended(&monic), Some(code));
+        }
+    }
+}
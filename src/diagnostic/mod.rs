@@ -0,0 +1,82 @@
+// エラーと警告
+
+use std::fmt;
+
+/// 診断の重大度
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// ソースの位置を指すエラーまたは警告
+/// * `line`と`column`は1から始まる。位置がない場合は`None`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file_name: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// `file_name`の`line`行目のエラーを作成する
+    pub fn error(file_name: &str, line: Option<usize>, message: String)
+        -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            file_name: file_name.to_string(),
+            line,
+            column: None,
+            message,
+        }
+    }
+
+    /// `file_name`の`line`行目の警告を作成する
+    pub fn warning(file_name: &str, line: Option<usize>, message: String)
+        -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(file_name, line, message)
+        }
+    }
+}
+
+/// `file:line:column: error: message`の形式で表示する
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file_name)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Diagnostic, Severity};
+
+    #[test]
+    fn test_diagnostic_display() {
+        let error = Diagnostic::error("Add.asm", Some(3), "test".to_string());
+        assert_eq!(error.to_string(), "Add.asm:3: error: test");
+
+        let mut warning = Diagnostic::warning("Add.asm", Some(3), "test".to_string());
+        assert_eq!(warning.severity, Severity::Warning);
+        warning.column = Some(5);
+        assert_eq!(warning.to_string(), "Add.asm:3:5: warning: test");
+
+        let error = Diagnostic::error("-", None, "test".to_string());
+        assert_eq!(error.to_string(), "-: error: test");
+    }
+}
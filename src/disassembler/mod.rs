@@ -0,0 +1,161 @@
+// 機械語からアセンブリ言語への変換
+
+use crate::isa::Isa;
+use crate::diagnostic::Diagnostic;
+
+/// `.hack`形式の文字列を読み、機械語のワード列を返す。空行は無視する
+pub fn read_hack(file_name: &str, hack: &str) -> Result<Vec<u16>, Vec<Diagnostic>> {
+    let mut words = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in hack.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.len() != 16 || !line.chars().all(|c| c == '0' || c == '1') {
+            errors.push(Diagnostic::error(file_name, Some(i + 1),
+                format!("`{}` is not a 16-bit binary word", line)));
+            continue;
+        }
+
+        words.push(u16::from_str_radix(line, 2).unwrap());
+    }
+
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+
+    Ok(words)
+}
+
+/// 1つの機械語をアセンブリ言語の命令に変換する。Hackの命令として解釈
+/// できない場合は`None`を返す。compは`isa`の命令セットで解釈する。destも
+/// jumpもない命令は、アセンブラがC命令として読めるように`=A`と書く
+pub fn instruction(word: u16, isa: &Isa) -> Option<String> {
+    if word & 0x8000 == 0 {
+        return Some(format!("@{}", word))
+    }
+
+    let bin = format!("{:016b}", word);
+    let comp = isa.comp_monic(&bin[..10])?;
+    let dest = isa.dest_monic(&bin[10..13])?;
+    let jump = isa.jump_monic(&bin[13..16])?;
+
+    let mut command = String::new();
+    if !dest.is_empty() || jump.is_empty() {
+        command += &dest;
+        command += "=";
+    }
+    command += &comp;
+    if !jump.is_empty() {
+        command += ";";
+        command += &jump;
+    }
+
+    Some(command)
+}
+
+/// 機械語のワード列をアセンブリ言語の文字列に変換する
+pub fn disassemble(file_name: &str, words: &[u16],
+                   isa: &Isa) -> Result<String, Vec<Diagnostic>> {
+    let mut asm = String::new();
+    let mut errors = Vec::new();
+
+    for (address, word) in words.iter().enumerate() {
+        match instruction(*word, isa) {
+            Some(command) => {
+                asm += &command;
+                asm += "\n";
+            },
+            None => errors.push(Diagnostic::error(file_name, Some(address + 1),
+                format!("{:016b} is not a Hack instruction", word))),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+
+    Ok(asm)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, instruction, read_hack};
+    use crate::diagnostic::Diagnostic;
+    use crate::isa::{Extension, Isa};
+    use crate::assembly::AssembleOptions;
+    use crate::assemble;
+
+    #[test]
+    fn test_read_hack() {
+        let hack = concat!("0000000000000010\n",
+                           "\n",
+                           "1110110000010000\n");
+        assert_eq!(read_hack("Add.hack", hack), Ok(vec![2, 0xec10]));
+
+        let hack = concat!("0000000000000010\n",
+                           "111011000001000\n",
+                           "111011000001000a\n");
+        assert_eq!(read_hack("Add.hack", hack).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_instruction() {
+        assert_eq!(&instruction(2, &Isa::default()).unwrap(), "@2");
+        assert_eq!(&instruction(0x7fff, &Isa::default()).unwrap(), "@32767");
+        assert_eq!(&instruction(0xec10, &Isa::default()).unwrap(), "D=A");
+        assert_eq!(&instruction(0xea87, &Isa::default()).unwrap(), "0;JMP");
+        assert_eq!(&instruction(0xfc1a, &Isa::default()).unwrap(), "MD=M;JEQ");
+        assert_eq!(&instruction(0xec00, &Isa::default()).unwrap(), "=A");
+        assert_eq!(instruction(0x8000, &Isa::default()), None);
+        assert_eq!(instruction(0xe040, &Isa::default()), None);
+    }
+
+    #[test]
+    fn test_instruction_extended_alu() {
+        let isa = Isa { extended_alu: true, ..Isa::default() };
+        assert_eq!(&instruction(0xec10, &isa).unwrap(), "D=A");
+        assert_eq!(&instruction(0xe5d0, &isa).unwrap(), "D=D+A+1");
+        assert_eq!(&instruction(0xef97, &isa).unwrap(), "D=-2;JMP");
+        assert_eq!(&instruction(0xe810, &isa).unwrap(), "D=%ALU(1,0,0,0,0,0)");
+        assert_eq!(&instruction(0xe040, &isa).unwrap(), "=!(D&A)");
+        assert_eq!(instruction(0xc040, &isa), None);
+    }
+
+    #[test]
+    fn test_instruction_extensions() {
+        let isa = Isa {
+            extended_alu: false,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        assert_eq!(&instruction(0xb810, &isa).unwrap(), "D=M<<");
+        assert_eq!(&instruction(0xa42a, &isa).unwrap(), "AM=D>>;JEQ");
+        assert_eq!(instruction(0xb810, &Isa::default()), None);
+    }
+
+    #[test]
+    fn test_instruction_round_trip() {
+        // 逆アセンブルした命令をアセンブルするともとの機械語に戻る
+        for extended_alu in [false, true] {
+            let isa = Isa { extended_alu, ..Isa::default() };
+            let words: Vec<u16> = (0x7ffe..=0xffff)
+                .filter(|word| instruction(*word, &isa).is_some())
+                .collect();
+            let options = AssembleOptions { isa: isa.clone(), ..AssembleOptions::default() };
+            let asm = disassemble("Test.hack", &words, &isa).unwrap();
+            assert_eq!(assemble("Test.asm", asm, &options).unwrap().words(), words);
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(&disassemble("Add.hack", &[2, 0xec10], &Isa::default()).unwrap(), "@2\nD=A\n");
+        assert_eq!(disassemble("Add.hack", &[2, 0x8000], &Isa::default()).unwrap_err(),
+                   vec![Diagnostic::error("Add.hack", Some(2),
+                        "1000000000000000 is not a Hack instruction".to_string())]);
+    }
+}
@@ -0,0 +1,237 @@
+// nand2tetris - 5章のHackコンピュータ
+
+use crate::isa::{Isa, Operation, Register};
+
+/// RAMのワード数
+pub const RAM_SIZE: usize = 0x8000;
+/// スクリーンのメモリマップの先頭の番地
+pub const SCREEN: usize = 0x4000;
+
+/// HackのCPUとメモリのエミュレータ。`rom`のプログラムを0番地から実行する
+pub struct Emulator {
+    rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    halted: bool, // 自分自身への無限ループに入ったか
+    isa: Isa,
+}
+
+/// ALUの計算をする。`control`はC命令の`c1`〜`c6`（zx, nx, zy, ny, f, no）
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & (1 << (5 - n)) != 0;
+
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+
+    if bit(5) { !out } else { out }
+}
+
+impl Emulator {
+    /// `rom`を読み込み、レジスタとRAMを0にした`Emulator`を作成する
+    #[cfg(test)]
+    pub fn new(rom: Vec<u16>) -> Emulator {
+        Emulator::with_isa(rom, Isa::default())
+    }
+
+    /// `isa`の拡張命令も実行する`Emulator`を作成する
+    pub fn with_isa(rom: Vec<u16>, isa: Isa) -> Emulator {
+        Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            halted: false,
+            isa,
+        }
+    }
+
+    /// プログラムの終わりに達しているか、`(END) @END 0;JMP`のような自分自身
+    /// への無限ループに入っているか？
+    pub fn halted(&self) -> bool {
+        self.halted || self.pc as usize >= self.rom.len()
+    }
+
+    /// 1命令を実行する。このルーチンは`halted()`が`false`の場合のみ呼ぶように
+    /// する
+    pub fn step(&mut self) {
+        let pc = self.pc as usize;
+        let word = self.rom[pc];
+
+        if word & 0x8000 == 0 {
+            self.a = word;
+            self.pc += 1;
+            return
+        }
+
+        let address = self.a as usize % RAM_SIZE;
+        let y = if word & 0x1000 != 0 { self.ram[address] } else { self.a };
+        let out = match self.isa.extra_comp(word) {
+            Some(comp) => {
+                let x = match comp.register {
+                    Register::A => self.a,
+                    Register::D => self.d,
+                    Register::M => self.ram[address],
+                };
+                match comp.operation {
+                    Operation::ShiftLeft => x << 1,
+                    Operation::ShiftRight => ((x as i16) >> 1) as u16,
+                }
+            },
+            None => alu(self.d, y, (word >> 6) & 0x3f),
+        };
+
+        if word & 0x0008 != 0 {
+            self.ram[address] = out;
+        }
+        if word & 0x0010 != 0 {
+            self.d = out;
+        }
+        let target = self.a;
+        if word & 0x0020 != 0 {
+            self.a = out;
+        }
+
+        let negative = out & 0x8000 != 0;
+        let jump = (word & 0x0004 != 0 && negative)
+            || (word & 0x0002 != 0 && out == 0)
+            || (word & 0x0001 != 0 && !negative && out != 0);
+        if !jump {
+            self.pc += 1;
+            return
+        }
+
+        self.pc = target;
+        self.halted = target as usize == pc
+            || (target as usize + 1 == pc && self.rom[target as usize] == target);
+    }
+
+    /// 停止するまで最大`cycles`命令を実行し、実行した命令数と停止したか
+    /// どうかを返す
+    pub fn run(&mut self, cycles: usize) -> (usize, bool) {
+        let mut count = 0;
+        while count < cycles && !self.halted() {
+            self.step();
+            count += 1;
+        }
+
+        (count, self.halted())
+    }
+
+    /// レジスタと、スクリーンより前のRAMのうち0でない番地を文字列にする
+    pub fn state(&self) -> String {
+        let mut state = format!("PC={} A={} D={}\n", self.pc, self.a, self.d as i16);
+        for (address, word) in self.ram[..SCREEN].iter().enumerate() {
+            if *word != 0 {
+                state += &format!("RAM[{}]={}\n", address, *word as i16);
+            }
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{alu, Emulator};
+    use crate::isa::{Extension, Isa};
+
+    #[test]
+    fn test_alu() {
+        let d = 5;
+        let a = 3;
+        assert_eq!(alu(d, a, 0b101010), 0);
+        assert_eq!(alu(d, a, 0b111111), 1);
+        assert_eq!(alu(d, a, 0b111010), 0xffff);
+        assert_eq!(alu(d, a, 0b001100), d);
+        assert_eq!(alu(d, a, 0b110000), a);
+        assert_eq!(alu(d, a, 0b001101), !d);
+        assert_eq!(alu(d, a, 0b001111), (-5_i16) as u16);
+        assert_eq!(alu(d, a, 0b011111), 6);
+        assert_eq!(alu(d, a, 0b110010), 2);
+        assert_eq!(alu(d, a, 0b000010), 8);
+        assert_eq!(alu(d, a, 0b010011), 2);
+        assert_eq!(alu(d, a, 0b000111), (-2_i16) as u16);
+        assert_eq!(alu(d, a, 0b000000), 1);
+        assert_eq!(alu(d, a, 0b010101), 7);
+    }
+
+    #[test]
+    fn test_alu_extended() {
+        let d = 5;
+        let a = 3;
+        assert_eq!(alu(d, a, 0b010111), 9); // D+A+1
+        assert_eq!(alu(d, a, 0b000110), 1); // D-A-1
+        assert_eq!(alu(d, a, 0b011110), (-7_i16) as u16); // -D-2
+        assert_eq!(alu(d, a, 0b111110), (-2_i16) as u16); // -2
+        assert_eq!(alu(d, a, 0b000001), !1); // !(D&A)
+        assert_eq!(alu(d, a, 0b010100), !7); // !(D|A)
+    }
+
+    #[test]
+    fn test_emulator_run() {
+        // @2 D=A @3 D=D+A @0 M=D (END) @END 0;JMP
+        let rom = vec![2, 0xec10, 3, 0xe090, 0, 0xe308, 6, 0xea87];
+        let mut emulator = Emulator::new(rom.clone());
+        assert_eq!(emulator.run(100), (8, true));
+        assert_eq!(emulator.ram[0], 5);
+        assert_eq!(emulator.d, 5);
+        assert_eq!(emulator.pc, 6);
+
+        let mut emulator = Emulator::new(rom);
+        assert_eq!(emulator.run(3), (3, false));
+        assert_eq!(emulator.pc, 3);
+    }
+
+    #[test]
+    fn test_emulator_jump() {
+        // @R0 D=M @POSITIVE D;JGT @R1 M=-1 (END) @END 0;JMP
+        // (POSITIVE) @R1 M=1 @END 0;JMP
+        let rom = vec![0, 0xfc10, 8, 0xe301, 1, 0xee88, 6, 0xea87,
+                       1, 0xefc8, 6, 0xea87];
+        let mut emulator = Emulator::new(rom.clone());
+        emulator.ram[0] = 3;
+        assert!(emulator.run(100).1);
+        assert_eq!(emulator.ram[1], 1);
+
+        let mut emulator = Emulator::new(rom);
+        emulator.ram[0] = 0;
+        assert!(emulator.run(100).1);
+        assert_eq!(emulator.ram[1], 0xffff);
+    }
+
+    #[test]
+    fn test_emulator_extensions() {
+        // @R0 D=M<< M=D>> A=D>>
+        let isa = Isa {
+            extended_alu: false,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        let mut emulator = Emulator::with_isa(vec![0, 0xb810, 0xa408, 0xa420], isa);
+        emulator.ram[0] = 0xc001;
+        emulator.run(100);
+        assert_eq!(emulator.d, 0x8002);
+        assert_eq!(emulator.ram[0], 0xc001);
+        assert_eq!(emulator.a, 0xc001);
+    }
+
+    #[test]
+    fn test_emulator_end_of_rom() {
+        let mut emulator = Emulator::new(vec![7]);
+        assert_eq!(emulator.run(100), (1, true));
+        assert_eq!(emulator.a, 7);
+    }
+    #[test]
+    fn test_emulator_state() {
+        let mut emulator = Emulator::new(vec![0, 0xee88, 2, 0xe308]);
+        assert_eq!(emulator.state(), "PC=0 A=0 D=0\n");
+        emulator.run(100);
+        assert_eq!(emulator.state(), "PC=4 A=2 D=0\nRAM[0]=-1\n");
+    }
+}
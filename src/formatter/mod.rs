@@ -0,0 +1,201 @@
+// アセンブリ言語のソースの整形
+
+use crate::code::Code;
+use crate::parser::CommentSyntax;
+use crate::syntax::{tokenize, Token, TokenKind};
+
+/// 命令の字下げ
+const INDENT: &str = "    ";
+
+/// 整形した1行
+/// * `code`はラベルまたは命令（字下げを含む）。コメントだけの行は`None`
+/// * `comment`は`code`の後ろに揃えて置くコメント
+struct Line {
+    code: Option<String>,
+    comment: Option<String>,
+}
+
+/// 記号の前後の空白を除いた命令`command`を整形する。C命令のcompは空白を除いて
+/// 本の書き方に直す。destとjumpの中の空白は、アセンブラが報告できるように残す
+fn format_command(command: &str) -> String {
+    if command.starts_with('@') || !(command.contains('=') || command.contains(';')) {
+        return command.to_string()
+    }
+
+    let (dest, rest) = match command.split_once('=') {
+        Some((dest, rest)) => (format!("{}=", dest), rest),
+        None => (String::new(), command),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+        Some((comp, jump)) => (comp, format!(";{}", jump)),
+        None => (rest, String::new()),
+    };
+    let comp: String = comp.split_whitespace().collect();
+    let comp = Code::canonical_comp(&comp).unwrap_or(comp);
+
+    format!("{}{}{}", dest, comp, jump)
+}
+
+/// `code`の最後の行の文字数を返す。複数行のブロックコメントを含む場合は最後の
+/// 改行の後ろだけを数える
+fn last_line_width(code: &str) -> usize {
+    code.rsplit('\n').next().unwrap_or("").chars().count()
+}
+
+/// 1行分の字句`tokens`を整形する
+fn format_line(tokens: &[Token]) -> Line {
+    let first_code = tokens.iter().position(|t| !t.kind.is_trivia());
+    let comments = |tokens: &[Token]| {
+        let comments: Vec<&str> = tokens.iter()
+            .filter(|t| t.kind.is_comment())
+            .map(|t| t.text.trim_end())
+            .collect();
+        if comments.is_empty() { None } else { Some(comments.join(" ")) }
+    };
+
+    let first_code = match first_code {
+        Some(n) => n,
+        None => {
+            // コメントだけの行は元の字下げの有無を保つ
+            let comment = comments(tokens).map(|comment| {
+                if tokens[0].kind.is_comment() { comment } else { format!("{}{}", INDENT, comment) }
+            });
+            return Line { code: None, comment }
+        }
+    };
+
+    // 命令の字句をつなげる。`@`、`(`、`)`、`=`、`;`の前後の空白は取り除き、
+    // 語の間（`goto LOOP`や`( my label )`など）は1つの空白にする
+    let code: Vec<&Token> = tokens.iter().filter(|t| !t.kind.is_trivia()).collect();
+    let mut command = code[0].text.clone();
+    for pair in code.windows(2) {
+        if pair[0].span.end != pair[1].span.start
+           && pair[0].kind != TokenKind::Punct && pair[1].kind != TokenKind::Punct {
+            command.push(' ');
+        }
+        command += &pair[1].text;
+    }
+    let is_label = command.starts_with('(') && !command.contains('=') && !command.contains(';');
+    let command = format_command(&command);
+
+    let mut formatted = if is_label { String::new() } else { INDENT.to_string() };
+    if let Some(leading) = comments(&tokens[..first_code]) {
+        formatted += &leading;
+        formatted.push(' ');
+    }
+    formatted += &command;
+
+    Line { code: Some(formatted), comment: comments(&tokens[first_code..]) }
+}
+
+/// アセンブリ言語のソースを`comments`の書き方で読み、整形する。ラベルは行頭に
+/// 置き、命令は字下げする。C命令の`=`と`;`の前後の空白は取り除き、compは本の
+/// 書き方（`M+D`は`D+M`）に直す。コメントはすべて残し、連続する行の行末の
+/// コメントは列を揃える。連続する空行は1行にまとめる。`ignore_case`は`tokenize`と同じ
+pub fn format(asm: &str, comments: CommentSyntax, ignore_case: bool) -> String {
+    let tokens: Vec<Token> = tokenize(asm, comments, ignore_case).into_iter()
+        .filter(|t| t.kind != TokenKind::Bom)
+        .collect();
+    let mut lines: Vec<Line> = Vec::new();
+    for tokens in tokens.split(|t| t.kind == TokenKind::Newline) {
+        let line = format_line(tokens);
+        let blank = line.code.is_none() && line.comment.is_none();
+        if blank && lines.last().is_none_or(|l| l.code.is_none() && l.comment.is_none()) {
+            continue;
+        }
+        lines.push(line);
+    }
+
+    while lines.last().is_some_and(|l| l.code.is_none() && l.comment.is_none()) {
+        lines.pop();
+    }
+
+    let mut output = String::new();
+    for run in lines.chunk_by(|a, b| a.code.is_some() == b.code.is_some()) {
+        // 命令が続く行では行末のコメントの列を揃える
+        let width = run.iter()
+            .filter(|l| l.comment.is_some())
+            .filter_map(|l| l.code.as_deref().map(last_line_width))
+            .max()
+            .unwrap_or(0);
+
+        for line in run {
+            match (&line.code, &line.comment) {
+                (Some(code), Some(comment)) => {
+                    let padding = width - last_line_width(code);
+                    output += &format!("{}{:padding$} {}\n", code, "", comment, padding = padding);
+                },
+                (Some(code), None) => output += &format!("{}\n", code),
+                (None, Some(comment)) => output += &format!("{}\n", comment),
+                (None, None) => output.push('\n'),
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::format;
+    use crate::parser::CommentSyntax;
+
+    #[test]
+    fn test_format() {
+        let comments = CommentSyntax::default();
+        assert_eq!(format("", comments, false), "");
+
+        let asm = concat!("// Adds 2 and 3\n",
+                          "\n",
+                          "\n",
+                          "  @2\n",
+                          "D=A   // D = 2\n",
+                          "    (END)\n",
+                          "\t@END\n",
+                          "  // loop\n",
+                          "0;JMP\n",
+                          "\n");
+        assert_eq!(format(asm, comments, false), concat!("// Adds 2 and 3\n",
+                                                  "\n",
+                                                  "    @2\n",
+                                                  "    D=A // D = 2\n",
+                                                  "(END)\n",
+                                                  "    @END\n",
+                                                  "    // loop\n",
+                                                  "    0;JMP\n"));
+    }
+
+    #[test]
+    fn test_format_commands() {
+        let comments = CommentSyntax::default();
+        let asm = concat!("\u{feff}( LOOP ) // start\r\n",
+                          "@ i // counter\r\n",
+                          "M = M + D ; JGT // add\r\n",
+                          "  goto   LOOP\r\n",
+                          "/* block\n",
+                          "   comment */ D;JMP\n",
+                          "AM=1+M /* keep */ // both\n");
+        assert_eq!(format(asm, comments, false), concat!("(LOOP)        // start\n",
+                                                  "    @i        // counter\n",
+                                                  "    M=D+M;JGT // add\n",
+                                                  "    goto LOOP\n",
+                                                  "    /* block\n",
+                                                  "   comment */ D;JMP\n",
+                                                  "    AM=M+1    /* keep */ // both\n"));
+
+        // 複数行のブロックコメントの後ろの命令は最後の行の幅で揃える
+        let asm = "/* a\n b */ @R0 // x\nD=A // y\n";
+        assert_eq!(format(asm, comments, false), concat!("    /* a\n",
+                                                  " b */ @R0 // x\n",
+                                                  "    D=A   // y\n"));
+
+        // シンボル、dest、jumpの中の空白はアセンブラが報告できるように残す
+        let asm = "( my label )\n@ my var\nA M = D + 1 ; J M P\n";
+        assert_eq!(format(asm, comments, false),
+                   "(my label)\n    @my var\n    A M=D+1;J M P\n");
+
+        let comments = CommentSyntax { block: true, semicolon: true };
+        assert_eq!(format("@R0 ; test\nD ; JGT\n", comments, false),
+                   "    @R0 ; test\n    D;JGT\n");
+    }
+}
@@ -0,0 +1,760 @@
+// 命令セットとその拡張
+
+use crate::code::Code;
+use crate::diagnostic::Diagnostic;
+
+/// 拡張命令が計算に使うレジスタ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A,
+    D,
+    M,
+}
+
+/// 拡張命令の演算
+/// * `ShiftLeft`は1ビットの論理左シフト
+/// * `ShiftRight`は1ビットの算術右シフト（符号を保つ）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// 拡張命令のcomp
+/// * `monic`はcompモーニック
+/// * `code`はビット15〜6のバイナリコード（先頭の3ビット + a + c1〜c6）
+/// * `operation`と`register`は命令の計算
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraComp {
+    pub monic: String,
+    pub code: String,
+    pub operation: Operation,
+    pub register: Register,
+}
+
+/// 名前で有効にする命令セットの拡張
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extension {
+    pub name: String,
+    pub comps: Vec<ExtraComp>,
+}
+
+/// シフト命令の拡張。C命令のビット13を0にした`101a`の領域を使う
+const SHIFT: [(&str, &str, Operation, Register); 6] = [
+    ("A<<", concat!("101", "0", "100000"), Operation::ShiftLeft, Register::A),
+    ("D<<", concat!("101", "0", "110000"), Operation::ShiftLeft, Register::D),
+    ("M<<", concat!("101", "1", "100000"), Operation::ShiftLeft, Register::M),
+    ("A>>", concat!("101", "0", "000000"), Operation::ShiftRight, Register::A),
+    ("D>>", concat!("101", "0", "010000"), Operation::ShiftRight, Register::D),
+    ("M>>", concat!("101", "1", "000000"), Operation::ShiftRight, Register::M),
+];
+
+impl Extension {
+    /// 組み込みの拡張を名前で探す。組み込みの拡張は`shift`だけ
+    pub fn builtin(name: &str) -> Option<Extension> {
+        let table = match name {
+            "shift" => &SHIFT,
+            _ => return None
+        };
+
+        let comps = table.iter()
+            .map(|(monic, code, operation, register)| ExtraComp {
+                monic: monic.to_string(),
+                code: code.to_string(),
+                operation: *operation,
+                register: *register,
+            })
+            .collect();
+
+        Some(Extension { name: name.to_string(), comps })
+    }
+}
+
+/// ファイルの表の1つの行（モーニック、バイナリコード、行番号）
+type Entry = (String, String, usize);
+/// ファイルの1つの表（表の名前、行番号、行の一覧）
+type Section = (String, usize, Vec<Entry>);
+
+/// ファイルから読み込んだ命令セットの対応表。`None`の表は本の対応表を使う
+/// * `dest`と`jump`のバイナリコードは3ビット
+/// * `comp`のバイナリコードはビット15〜6（先頭の3ビット + a + c1〜c6）
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IsaTable {
+    pub dest: Option<Vec<(String, String)>>,
+    pub comp: Option<Vec<(String, String)>>,
+    pub jump: Option<Vec<(String, String)>>,
+}
+
+/// JSONとTOMLに共通の1文字のエスケープ（`\n`の`n`など）が表す文字を返す
+fn escape_char(c: char) -> Option<char> {
+    match c {
+        'b' => Some('\u{8}'),
+        'f' => Some('\u{c}'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        '"' | '\\' => Some(c),
+        _ => None,
+    }
+}
+
+/// `\u`などの`escape`の後ろの`digits`桁の16進数を読む
+fn read_hex(chars: &mut impl Iterator<Item = char>, escape: char, digits: usize)
+    -> Result<u32, String> {
+    let hex: String = chars.take(digits).collect();
+    if hex.len() != digits || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid escape `\\{}{}`", escape, hex))
+    }
+    Ok(u32::from_str_radix(&hex, 16).unwrap())
+}
+
+/// JSONの文字列と区切り文字を読む
+struct Json<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Json<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            if *c == '\n' {
+                self.line += 1;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn found(c: Option<char>) -> String {
+        match c {
+            Some(c) => format!("`{}`", c),
+            None => "the end of the file".to_string(),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), (usize, String)> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            c => Err((self.line, format!("expected `{}` but found {}",
+                                         expected, Json::found(c)))),
+        }
+    }
+
+    /// `\u`の後ろの4桁の16進数を読む
+    fn hex4(&mut self) -> Result<u32, (usize, String)> {
+        read_hex(&mut self.chars, 'u', 4).map_err(|message| (self.line, message))
+    }
+
+    /// `\u`の後ろを読み、サロゲートペアは1文字にまとめる
+    fn unicode(&mut self) -> Result<char, (usize, String)> {
+        let unpaired = |line: usize, code: u32| {
+            Err((line, format!("unpaired surrogate `\\u{:04x}`", code)))
+        };
+        let code = match self.hex4()? {
+            high @ 0xd800..=0xdbff => {
+                if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                    return unpaired(self.line, high)
+                }
+                match self.hex4()? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => return unpaired(self.line, high),
+                }
+            },
+            low @ 0xdc00..=0xdfff => return unpaired(self.line, low),
+            code => code,
+        };
+        Ok(char::from_u32(code).unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, (usize, String)> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let c = match self.chars.next() {
+                None | Some('\n') => return Err((self.line, "unterminated string".to_string())),
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('/') => '/',
+                    Some('u') => self.unicode()?,
+                    c => match c.and_then(escape_char) {
+                        Some(c) => c,
+                        None => return Err((self.line,
+                            format!("invalid escape `\\` followed by {}", Json::found(c)))),
+                    },
+                },
+                Some(c) => c,
+            };
+            string.push(c);
+        }
+    }
+
+    /// `{ "key": ..., ... }`を読み、値は`member`で読む
+    fn object<F>(&mut self, mut member: F) -> Result<(), (usize, String)>
+    where F: FnMut(&mut Self, String, usize) -> Result<(), (usize, String)> {
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(())
+        }
+
+        loop {
+            self.skip_whitespace();
+            let line = self.line;
+            let key = self.string()?;
+            self.expect(':')?;
+            member(self, key, line)?;
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => (),
+                Some('}') => return Ok(()),
+                c => return Err((self.line, format!("expected `,` or `}}` but found {}",
+                                                    Json::found(c)))),
+            }
+        }
+    }
+}
+
+/// `{ "dest": { "M": "001", ... }, ... }`の形のJSONを読む
+fn parse_json(text: &str) -> Result<Vec<Section>, (usize, String)> {
+    let mut json = Json { chars: text.chars().peekable(), line: 1 };
+    let mut sections = Vec::new();
+
+    json.object(|json, name, line| {
+        let mut entries = Vec::new();
+        json.object(|json, monic, line| {
+            entries.push((monic, json.string()?, line));
+            Ok(())
+        })?;
+        sections.push((name, line, entries));
+        Ok(())
+    })?;
+
+    json.skip_whitespace();
+    if let Some(c) = json.chars.next() {
+        return Err((json.line, format!("unexpected `{}` after the object", c)))
+    }
+
+    Ok(sections)
+}
+
+/// TOMLの`\`の後ろを読み、エスケープが表す文字を返す
+fn toml_escape(chars: &mut impl Iterator<Item = char>) -> Result<char, String> {
+    match chars.next() {
+        Some(escape @ ('u' | 'U')) => {
+            let digits = if escape == 'u' { 4 } else { 8 };
+            let code = read_hex(chars, escape, digits)?;
+            char::from_u32(code).ok_or_else(|| {
+                format!("invalid escape `\\{}{:0width$x}`", escape, code, width = digits)
+            })
+        },
+        Some(c) => escape_char(c).ok_or_else(|| format!("invalid escape `\\{}`", c)),
+        None => Err("unterminated string".to_string()),
+    }
+}
+
+/// TOMLの`"..."`または`'...'`の文字列を読み、文字列と残りを返す。文字列で
+/// 始まらない場合は`None`、閉じていないかエスケープが誤っている場合はエラーを返す
+fn toml_string(text: &str) -> Option<Result<(String, &str), String>> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut string = String::new();
+    let mut chars = text[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some(Ok((string, &text[i + 2..]))),
+            '\\' if quote == '"' => match toml_escape(&mut chars.by_ref().map(|(_, c)| c)) {
+                Ok(c) => string.push(c),
+                Err(message) => return Some(Err(message)),
+            },
+            c => string.push(c),
+        }
+    }
+
+    Some(Err("unterminated string".to_string()))
+}
+
+/// `[dest]`と`M = "001"`の行からなるTOMLを読む。`#`から行末まではコメント
+fn parse_toml(text: &str) -> Result<Vec<Section>, (usize, String)> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = match name.split_once(']') {
+                Some((name, rest)) if rest.trim().is_empty() || rest.trim().starts_with('#') => name,
+                _ => return Err((line_number, format!("invalid table header `{}`", line))),
+            };
+            sections.push((name.trim().to_string(), line_number, Vec::new()));
+            continue;
+        }
+
+        let (monic, rest) = match toml_string(line) {
+            Some(Ok((monic, rest))) => (monic, rest),
+            Some(Err(message)) => return Err((line_number, message)),
+            None => {
+                let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(line.len());
+                (line[..end].to_string(), &line[end..])
+            },
+        };
+        let code = rest.trim_start().strip_prefix('=')
+            .and_then(|value| toml_string(value.trim_start()));
+        let code = match code {
+            Some(Err(message)) => return Err((line_number, message)),
+            Some(Ok((code, rest)))
+                if (rest.trim().is_empty() || rest.trim().starts_with('#'))
+                    && (!monic.is_empty() || line.starts_with(['"', '\''])) => code,
+            _ => return Err((line_number, format!("expected `key = \"value\"` but found `{}`",
+                                                  line))),
+        };
+
+        match sections.last_mut() {
+            Some((_, _, entries)) => entries.push((monic, code, line_number)),
+            None => return Err((line_number, format!("`{}` is outside of a table", line))),
+        }
+    }
+
+    Ok(sections)
+}
+
+impl IsaTable {
+    /// 命令セットの対応表のファイルを読む。`.json`で終わるファイルはJSON、
+    /// それ以外はTOMLとして読む
+    pub fn load(file_name: &str, text: &str) -> Result<IsaTable, Vec<Diagnostic>> {
+        let sections = if file_name.ends_with(".json") {
+            parse_json(text)
+        } else {
+            parse_toml(text)
+        };
+
+        match sections {
+            Ok(sections) => IsaTable::from_sections(file_name, sections),
+            Err((line, message)) => Err(vec![Diagnostic::error(file_name, Some(line), message)]),
+        }
+    }
+
+    /// 読み込んだ表を検査して`IsaTable`を作る。知らない表、バイナリコードの
+    /// 長さの誤り、A命令と区別できないcomp、モーニックの重複、同じバイナリ
+    /// コードを持つモーニックはエラーになる
+    fn from_sections(file_name: &str, sections: Vec<Section>) -> Result<IsaTable, Vec<Diagnostic>> {
+        let mut table = IsaTable::default();
+        let mut errors = Vec::new();
+        let mut error = |line: usize, message: String| {
+            errors.push(Diagnostic::error(file_name, Some(line), message));
+        };
+
+        for (name, line, entries) in sections {
+            let (slot, bits) = match name.as_str() {
+                "dest" => (&mut table.dest, 3),
+                "comp" => (&mut table.comp, 10),
+                "jump" => (&mut table.jump, 3),
+                _ => {
+                    error(line, format!("unknown table `{}`", name));
+                    continue;
+                }
+            };
+            if slot.is_some() {
+                error(line, format!("table `{}` is defined twice", name));
+                continue;
+            }
+
+            let mut rows: Vec<(String, String, usize)> = Vec::new();
+            for (monic, code, line) in entries {
+                if monic.contains(['=', ';']) || monic.contains(char::is_whitespace)
+                   || (name == "comp" && monic.is_empty()) {
+                    error(line, format!("invalid {} mnemonic `{}`", name, monic));
+                } else if code.len() != bits || !code.chars().all(|c| c == '0' || c == '1') {
+                    error(line, format!("code `{}` of {} `{}` must be {} binary digits",
+                                        code, name, monic, bits));
+                } else if name == "comp" && code.starts_with('0') {
+                    error(line, format!("code `{}` of comp `{}` would be an A-instruction",
+                                        code, monic));
+                } else if let Some((_, _, first)) = rows.iter().find(|(m, _, _)| *m == monic) {
+                    error(line, format!("{} `{}` is already defined on line {}",
+                                        name, monic, first));
+                } else if let Some((other, _, first)) = rows.iter().find(|(_, c, _)| *c == code) {
+                    error(line, format!("{} `{}` has the same code `{}` as `{}` on line {}",
+                                        name, monic, code, other, first));
+                } else {
+                    rows.push((monic, code, line));
+                }
+            }
+
+            if name != "comp" && !rows.iter().any(|(monic, _, _)| monic.is_empty()) {
+                error(line, format!("table `{}` must define the empty mnemonic `\"\"`", name));
+            }
+            *slot = Some(rows.into_iter().map(|(monic, code, _)| (monic, code)).collect());
+        }
+
+        if !errors.is_empty() {
+            return Err(errors)
+        }
+
+        Ok(table)
+    }
+}
+
+/// 対応表からモーニックのバイナリコードを探す
+fn to_code(table: &[(String, String)], monic: &str) -> Option<String> {
+    table.iter()
+        .find(|(m, _)| m == monic)
+        .map(|(_, code)| code.clone())
+}
+
+/// 対応表からバイナリコードのモーニックを探す
+fn to_monic(table: &[(String, String)], code: &str) -> Option<String> {
+    table.iter()
+        .find(|(_, c)| c == code)
+        .map(|(monic, _)| monic.clone())
+}
+
+/// アセンブラ、逆アセンブラ、エミュレータが共通で使う命令セット
+/// * `extended_alu`は本に載っていないALUの組み合わせを扱うかどうか
+/// * `extensions`は有効にした拡張
+/// * `table`はファイルから読み込んだ対応表。compの表と`extended_alu`は
+///   同時に使えない
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Isa {
+    pub extended_alu: bool,
+    pub extensions: Vec<Extension>,
+    pub table: IsaTable,
+}
+
+impl Isa {
+    /// destモーニックのバイナリコードを返す
+    pub fn dest(&self, monic: &str) -> Option<String> {
+        match &self.table.dest {
+            Some(dest) => to_code(dest, monic)
+                .or_else(|| to_code(dest, &Code::canonical_dest(monic)?)),
+            None => Code::dest(monic),
+        }
+    }
+
+    /// jumpモーニックのバイナリコードを返す
+    pub fn jump(&self, monic: &str) -> Option<String> {
+        match &self.table.jump {
+            Some(jump) => to_code(jump, monic),
+            None => Code::jump(monic),
+        }
+    }
+
+    /// destのバイナリコードのモーニックを返す
+    pub fn dest_monic(&self, code: &str) -> Option<String> {
+        match &self.table.dest {
+            Some(dest) => to_monic(dest, code),
+            None => Code::dest_monic(code),
+        }
+    }
+
+    /// jumpのバイナリコードのモーニックを返す
+    pub fn jump_monic(&self, code: &str) -> Option<String> {
+        match &self.table.jump {
+            Some(jump) => to_monic(jump, code),
+            None => Code::jump_monic(code),
+        }
+    }
+
+    /// 拡張を除いたcompモーニックのビット15〜6のバイナリコードを返す
+    fn base_comp(&self, monic: &str) -> Option<String> {
+        if let Some(comp) = &self.table.comp {
+            return to_code(comp, monic).or_else(|| {
+                Code::commuted(monic).iter().find_map(|swapped| to_code(comp, swapped))
+            })
+        }
+
+        let comp = if self.extended_alu {
+            Code::comp_extended(monic)
+        } else {
+            Code::comp(monic)
+        };
+        comp.map(|comp| format!("111{}", comp))
+    }
+
+    /// 拡張を除いたビット15〜6のバイナリコードのcompモーニックを返す
+    fn base_comp_monic(&self, code: &str) -> Option<String> {
+        if let Some(comp) = &self.table.comp {
+            return to_monic(comp, code)
+        }
+
+        let comp = code.strip_prefix("111")?;
+        if self.extended_alu {
+            Code::comp_monic_extended(comp)
+        } else {
+            Code::comp_monic(comp)
+        }
+    }
+
+    /// compモーニックのビット15〜6のバイナリコードを返す
+    pub fn comp(&self, monic: &str) -> Option<String> {
+        self.base_comp(monic).or_else(|| {
+            self.extra_comps()
+                .find(|c| c.monic == monic)
+                .map(|c| c.code.clone())
+        })
+    }
+
+    /// ビット15〜6のバイナリコードのcompモーニックを返す
+    pub fn comp_monic(&self, code: &str) -> Option<String> {
+        self.base_comp_monic(code).or_else(|| {
+            self.extra_comps()
+                .find(|c| c.code == code)
+                .map(|c| c.monic.clone())
+        })
+    }
+
+    /// 機械語が拡張命令であればそのcompを返す
+    pub fn extra_comp(&self, word: u16) -> Option<&ExtraComp> {
+        let code = format!("{:010b}", word >> 6);
+        if self.base_comp_monic(&code).is_some() {
+            return None
+        }
+
+        self.extra_comps().find(|c| c.code == code)
+    }
+
+    /// 拡張の命令が対応表や他の拡張とモーニックまたはバイナリコードで
+    /// 衝突していればそのエラーメッセージを返す。compの表と`extended_alu`を
+    /// 両方指定した場合も衝突として返す
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        if self.extended_alu && self.table.comp.is_some() {
+            conflicts.push("--extended-alu can't be used with a comp table".to_string());
+        }
+        let mut seen: Vec<(&str, &ExtraComp)> = Vec::new();
+
+        for extension in &self.extensions {
+            for comp in &extension.comps {
+                if let Some(code) = self.base_comp(&comp.monic) {
+                    conflicts.push(format!("`{}` of extension `{}` is already comp `{}`",
+                                           comp.monic, extension.name, code));
+                } else if let Some(monic) = self.base_comp_monic(&comp.code) {
+                    conflicts.push(format!("code `{}` of `{}` in extension `{}` is already \
+                                            used by `{}`",
+                                           comp.code, comp.monic, extension.name, monic));
+                } else if let Some((name, other)) = seen.iter()
+                    .find(|(_, c)| c.monic == comp.monic || c.code == comp.code) {
+                    conflicts.push(format!("`{}` of extension `{}` conflicts with `{}` of \
+                                            extension `{}`",
+                                           comp.monic, extension.name, other.monic, name));
+                }
+                seen.push((&extension.name, comp));
+            }
+        }
+
+        conflicts
+    }
+
+    fn extra_comps(&self) -> impl Iterator<Item = &ExtraComp> {
+        self.extensions.iter().flat_map(|e| e.comps.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Extension, Isa, IsaTable, Operation, Register};
+    use crate::diagnostic::Diagnostic;
+
+    #[test]
+    fn test_isa_comp() {
+        let isa = Isa::default();
+        assert_eq!(&isa.comp("D+A").unwrap(), "1110000010");
+        assert_eq!(isa.comp("D<<"), None);
+        assert_eq!(isa.comp("-2"), None);
+
+        let isa = Isa {
+            extended_alu: true,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        assert_eq!(&isa.comp("D<<").unwrap(), "1010110000");
+        assert_eq!(&isa.comp("M>>").unwrap(), "1011000000");
+        assert_eq!(&isa.comp("-2").unwrap(), "1110111110");
+    }
+
+    #[test]
+    fn test_isa_comp_monic() {
+        let isa = Isa::default();
+        assert_eq!(&isa.comp_monic("1110000010").unwrap(), "D+A");
+        assert_eq!(isa.comp_monic("1010110000"), None);
+
+        let isa = Isa {
+            extended_alu: false,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        assert_eq!(&isa.comp_monic("1010110000").unwrap(), "D<<");
+        assert_eq!(&isa.comp_monic("1010000000").unwrap(), "A>>");
+        assert_eq!(isa.comp_monic("1000000000"), None);
+    }
+
+    #[test]
+    fn test_isa_extra_comp() {
+        let isa = Isa {
+            extended_alu: false,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        // D=M<<
+        let comp = isa.extra_comp(0xb810).unwrap();
+        assert_eq!(comp.operation, Operation::ShiftLeft);
+        assert_eq!(comp.register, Register::M);
+        assert_eq!(isa.extra_comp(0xec10), None);
+        assert_eq!(Isa::default().extra_comp(0xb810), None);
+        assert_eq!(Extension::builtin("rotate"), None);
+    }
+
+    fn strings(table: &[(&str, &str)]) -> Vec<(String, String)> {
+        table.iter().map(|(m, c)| (m.to_string(), c.to_string())).collect()
+    }
+
+    #[test]
+    fn test_isa_table_load_toml() {
+        let toml = concat!("# 2ビットのdestを持つCPU\n",
+                           "[dest]\n",
+                           "\"\" = \"000\"\n",
+                           "D = \"001\"  # Dだけ\n",
+                           "\n",
+                           "[comp]\n",
+                           "'D+1' = \"1110011111\"\n",
+                           "\"D<<\" = \"1000000000\"\n");
+        assert_eq!(IsaTable::load("cpu.toml", toml), Ok(IsaTable {
+            dest: Some(strings(&[("", "000"), ("D", "001")])),
+            comp: Some(strings(&[("D+1", "1110011111"), ("D<<", "1000000000")])),
+            jump: None,
+        }));
+
+        assert_eq!(IsaTable::load("cpu.toml", "D = \"001\"\n"), Err(vec![
+            Diagnostic::error("cpu.toml", Some(1), "`D = \"001\"` is outside of a table".to_string()),
+        ]));
+        assert_eq!(IsaTable::load("cpu.toml", "[dest]\nD = 001\n"), Err(vec![
+            Diagnostic::error("cpu.toml", Some(2),
+                "expected `key = \"value\"` but found `D = 001`".to_string()),
+        ]));
+
+        // 基本文字列のエスケープはJSONと同じ文字を受け付け、\Uは8桁で読む
+        let message = |toml: &str| IsaTable::load("cpu.toml", toml).unwrap_err()[0].message.clone();
+        assert_eq!(message(r#"[dest]
+"\b\f\r\n\t\"\\\u00e9\U0001F600" = "000""#),
+                   "invalid dest mnemonic `\u{8}\u{c}\r\n\t\"\\\u{e9}\u{1f600}`");
+        assert_eq!(message("[dest]\n\"\\x\" = \"000\"\n"), "invalid escape `\\x`");
+        assert_eq!(message("[dest]\nD = \"\\u00g1\"\n"), "invalid escape `\\u00g1`");
+        assert_eq!(message("[dest]\nD = \"\\ud800\"\n"), "invalid escape `\\ud800`");
+        assert_eq!(message("[dest]\nD = \"\\U00110000\"\n"), "invalid escape `\\U00110000`");
+        assert_eq!(message("[dest]\nD = \"001\n"), "unterminated string");
+    }
+
+    #[test]
+    fn test_isa_table_load_json() {
+        let json = concat!("{\n",
+                           "  \"jump\": { \"\": \"000\", \"JMP\": \"111\" },\n",
+                           "  \"comp\": {\n",
+                           "    \"0\": \"1110101010\"\n",
+                           "  }\n",
+                           "}\n");
+        assert_eq!(IsaTable::load("cpu.json", json), Ok(IsaTable {
+            dest: None,
+            comp: Some(strings(&[("0", "1110101010")])),
+            jump: Some(strings(&[("", "000"), ("JMP", "111")])),
+        }));
+
+        assert_eq!(IsaTable::load("cpu.json", "{\n  \"comp\": { \"0\" \"1110101010\" }\n}"),
+                   Err(vec![Diagnostic::error("cpu.json", Some(2),
+                       "expected `:` but found `\"`".to_string())]));
+        assert_eq!(IsaTable::load("cpu.json", "{ \"comp\": {} } }").unwrap_err().len(), 1);
+
+        // エスケープはJSONのすべての書き方を受け付け、サロゲートペアは1文字にする
+        let message = |json: &str| IsaTable::load("cpu.json", json).unwrap_err()[0].message.clone();
+        assert_eq!(message(r#"{ "dest": { "\b\f\r\n\t\"\\\/\u00e9\ud83d\ude00": "000" } }"#),
+                   "invalid dest mnemonic `\u{8}\u{c}\r\n\t\"\\/\u{e9}\u{1f600}`");
+        assert_eq!(message(r#"{ "\u00g1": {} }"#), "invalid escape `\\u00g1`");
+        assert_eq!(message(r#"{ "\u+0e9": {} }"#), "invalid escape `\\u+0e9`");
+        assert_eq!(message(r#"{ "\ud83d": {} }"#), "unpaired surrogate `\\ud83d`");
+        assert_eq!(message(r#"{ "\ud83d\u0041": {} }"#), "unpaired surrogate `\\ud83d`");
+        assert_eq!(message(r#"{ "\ude00": {} }"#), "unpaired surrogate `\\ude00`");
+        assert_eq!(IsaTable::load("cpu.json", "{ \"comp\": { \"0\": \"1110").unwrap_err(),
+                   vec![Diagnostic::error("cpu.json", Some(1),
+                       "unterminated string".to_string())]);
+    }
+
+    #[test]
+    fn test_isa_table_validation() {
+        let toml = concat!("[dest]\n",
+                           "M = \"001\"\n",
+                           "D = \"01\"\n",
+                           "[comp]\n",
+                           "\"0\" = \"1110101010\"\n",
+                           "\"0\" = \"1110111111\"\n",
+                           "\"Z\" = \"1110101010\"\n",
+                           "\"1\" = \"0110111111\"\n",
+                           "\"A=B\" = \"1110110000\"\n",
+                           "[dest]\n",
+                           "[timer]\n");
+        let errors: Vec<String> = IsaTable::load("cpu.toml", toml).unwrap_err()
+            .into_iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec![
+            "cpu.toml:3: error: code `01` of dest `D` must be 3 binary digits",
+            "cpu.toml:1: error: table `dest` must define the empty mnemonic `\"\"`",
+            "cpu.toml:6: error: comp `0` is already defined on line 5",
+            "cpu.toml:7: error: comp `Z` has the same code `1110101010` as `0` on line 5",
+            "cpu.toml:8: error: code `0110111111` of comp `1` would be an A-instruction",
+            "cpu.toml:9: error: invalid comp mnemonic `A=B`",
+            "cpu.toml:10: error: table `dest` is defined twice",
+            "cpu.toml:11: error: unknown table `timer`",
+        ]);
+    }
+
+    #[test]
+    fn test_isa_with_table() {
+        let toml = concat!("[dest]\n",
+                           "\"\" = \"000\"\n",
+                           "D = \"001\"\n",
+                           "AD = \"011\"\n",
+                           "[comp]\n",
+                           "\"D+A\" = \"1100000010\"\n",
+                           "\"-1\" = \"1100111010\"\n");
+        let isa = Isa {
+            table: IsaTable::load("cpu.toml", toml).unwrap(),
+            ..Isa::default()
+        };
+        assert_eq!(&isa.dest("DA").unwrap(), "011");
+        assert_eq!(isa.dest("M"), None);
+        assert_eq!(&isa.comp("A+D").unwrap(), "1100000010");
+        assert_eq!(isa.comp("D"), None);
+        assert_eq!(&isa.jump("JMP").unwrap(), "111");
+        assert_eq!(&isa.dest_monic("001").unwrap(), "D");
+        assert_eq!(&isa.comp_monic("1100111010").unwrap(), "-1");
+        assert_eq!(isa.comp_monic("1110111010"), None);
+        assert!(isa.conflicts().is_empty());
+
+        let isa = Isa {
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            table: IsaTable::load("cpu.toml", "[comp]\n\"D+1\" = \"1010110000\"\n").unwrap(),
+            ..Isa::default()
+        };
+        assert_eq!(isa.conflicts(), vec![
+            "code `1010110000` of `D<<` in extension `shift` is already used by `D+1`",
+        ]);
+
+        let isa = Isa {
+            extended_alu: true,
+            table: IsaTable::load("cpu.toml", "[comp]\n\"D+1\" = \"1110011111\"\n").unwrap(),
+            ..Isa::default()
+        };
+        assert_eq!(isa.conflicts(), vec!["--extended-alu can't be used with a comp table"]);
+
+        let isa = Isa {
+            extensions: vec![Extension::builtin("shift").unwrap(),
+                             Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        };
+        assert_eq!(isa.conflicts().len(), 6);
+    }
+}
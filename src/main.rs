@@ -1,21 +1,110 @@
 // nand2tetris - 6章
 
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 mod parser;
 use parser::Parser;
 use parser::CommandType;
+use parser::invalid_symbol_char;
 mod code;
 use code::Code;
 mod symbol_table;
-use symbol_table::SymbolTable;
+use symbol_table::{SymbolKind, SymbolTable, PREDEFINED};
+mod output;
+use output::{Format, Output};
+mod assembly;
+use assembly::{Assembly, AssembleOptions, Instruction, VariableOrder};
+mod diagnostic;
+use diagnostic::{Diagnostic, Severity};
+mod cli;
+use cli::{Action, Cli, Subcommand, Warnings};
+use cli::{EXIT_OK, EXIT_USAGE, EXIT_DATA, EXIT_NO_INPUT, EXIT_CANT_CREATE};
+mod disassembler;
+mod emulator;
+use emulator::Emulator;
+mod formatter;
+mod syntax;
+mod xref;
+mod pseudo;
+mod isa;
+use isa::IsaTable;
+#[cfg(test)]
+use isa::{Extension, Isa};
+#[cfg(test)]
+use pseudo::Stack;
 
+/// 入力ファイル名から出力ファイル名を作る。`Foo.asm`は`format`の拡張子の
+/// `Foo.hack`や`Foo.mif`になり、標準入力（`-`）の場合は標準出力（`-`）になる
+fn default_output_file_name(file_name: &str, format: Format) -> String {
+    if file_name == "-" {
+        return "-".to_string()
+    }
+
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => stem,
+        _ => file_name,
+    };
+
+    format!("{}.{}", stem, format.extension())
+}
+#[test]
+fn test_default_output_file_name() {
+    assert_eq!(default_output_file_name("Add.asm", Format::Hack), "Add.hack");
+    assert_eq!(default_output_file_name("dir/Add.asm", Format::Hack), "dir/Add.hack");
+    assert_eq!(default_output_file_name("dir.d/Add", Format::Hack), "dir.d/Add.hack");
+    assert_eq!(default_output_file_name("Add", Format::Hack), "Add.hack");
+    assert_eq!(default_output_file_name("-", Format::Hack), "-");
+    assert_eq!(default_output_file_name("Add.asm", Format::Mif), "Add.mif");
+    assert_eq!(default_output_file_name("Add.asm", Format::Digital), "Add.hex");
+}
+
+/// `file_name`の内容を読む。`file_name`が`-`の場合は標準入力から読む
+fn read_input(file_name: &str) -> io::Result<String> {
+    let mut asm = String::new();
+    if file_name == "-" {
+        io::stdin().read_to_string(&mut asm)?;
+    } else {
+        File::open(file_name)?.read_to_string(&mut asm)?;
+    }
+
+    Ok(asm)
+}
+
+/// `output`を`file_name`に書き込む。`file_name`が`-`の場合は標準出力に書く。
+/// ファイルの中身がすでに`output`と同じ場合は、`--watch`が更新日時の変化で
+/// 再実行し続けないように書き込まない
+fn write_output(file_name: &str, output: &str) -> io::Result<()> {
+    if file_name == "-" {
+        io::stdout().write_all(output.as_bytes())
+    } else if fs::read(file_name).is_ok_and(|old| old == output.as_bytes()) {
+        Ok(())
+    } else {
+        File::create(file_name)?.write_all(output.as_bytes())
+    }
+}
+#[test]
+fn test_write_output() {
+    let file_name = env::temp_dir().join(format!("write_output_{}.asm", process::id()));
+    let file_name = file_name.to_string_lossy().to_string();
+    write_output(&file_name, "@0\n").unwrap();
+    File::open(&file_name).unwrap().set_modified(SystemTime::UNIX_EPOCH).unwrap();
 
-fn print_usage() {
-    println!("Usage: command <filename> <output filename>");
+    // 同じ中身は書き込まず、更新日時も変わらない
+    write_output(&file_name, "@0\n").unwrap();
+    let modified = || fs::metadata(&file_name).unwrap().modified().unwrap();
+    assert_eq!(modified(), SystemTime::UNIX_EPOCH);
+    write_output(&file_name, "@1\n").unwrap();
+    assert_ne!(modified(), SystemTime::UNIX_EPOCH);
+    assert_eq!(fs::read_to_string(&file_name).unwrap(), "@1\n");
+    fs::remove_file(&file_name).unwrap();
 }
 
 /// 数字を16bitのバイナリへ変換する
@@ -40,42 +129,36 @@ fn test_number_to_16bin() {
 }
 
 /// symbol tableを作成する。このsymbol tableに変数シンボルは含まれない。
-fn get_symbol_table(asm: &str) -> SymbolTable {
-    let mut parser = Parser::new(asm.to_string());
-    let mut stable = SymbolTable::new();
+fn get_symbol_table(asm: &str, options: &AssembleOptions) -> SymbolTable {
+    let mut parser = Parser::with_options(asm.to_string(), options.comments, options.stack,
+                                          options.ignore_case);
+    let mut stable = if options.ignore_symbol_case {
+        SymbolTable::case_insensitive()
+    } else {
+        SymbolTable::new()
+    };
     let mut count = 0; // コマンド数のカウンター
 
     // 定義済みシンボルの設定
-    stable.add_entry("SP", 0);
-    stable.add_entry("LCL", 1);
-    stable.add_entry("ARG", 2);
-    stable.add_entry("THIS", 3);
-    stable.add_entry("THAT", 4);
-    stable.add_entry("R0", 0);
-    stable.add_entry("R1", 1);
-    stable.add_entry("R2", 2);
-    stable.add_entry("R3", 3);
-    stable.add_entry("R4", 4);
-    stable.add_entry("R5", 5);
-    stable.add_entry("R6", 6);
-    stable.add_entry("R7", 7);
-    stable.add_entry("R8", 8);
-    stable.add_entry("R9", 9);
-    stable.add_entry("R10", 10);
-    stable.add_entry("R11", 11);
-    stable.add_entry("R12", 12);
-    stable.add_entry("R13", 13);
-    stable.add_entry("R14", 14);
-    stable.add_entry("R15", 15);
-    stable.add_entry("SCREEN", 0x4000);
-    stable.add_entry("KBD", 0x6000);
+    if !options.no_predefined {
+        for (symbol, address) in PREDEFINED {
+            stable.add_symbol(symbol, address, SymbolKind::Predefined, None);
+        }
+    }
 
     while parser.has_more_commands() {
         parser.advance();
 
         match parser.command_type() {
             CommandType::A | CommandType::C => count += 1,
-            CommandType::L => stable.add_entry(&parser.symbol(), count),
+            // 重複したラベルはassembleでエラーにする
+            CommandType::L if parser.pseudo().is_some() => {
+                stable.add_symbol(&parser.symbol(), count, SymbolKind::Internal,
+                                  Some(parser.line_number()));
+            },
+            CommandType::L => {
+                let _ = stable.add_label(&parser.symbol(), count, parser.line_number());
+            },
             CommandType::None => ()
         }
     }
@@ -84,17 +167,18 @@ fn get_symbol_table(asm: &str) -> SymbolTable {
 }
 #[test]
 fn test_get_symbol_table() {
+    let options = AssembleOptions::default();
     let asm = r#"
     @10
     "#.to_string();
-    assert_eq!(get_symbol_table(&asm), get_symbol_table(""));
+    assert_eq!(get_symbol_table(&asm, &options), get_symbol_table("", &options));
 
     let asm = r#"
     (TEST)
     "#.to_string();
-    let mut stable = get_symbol_table("");
+    let mut stable = get_symbol_table("", &options);
     stable.add_entry("TEST", 0);
-    assert_eq!(get_symbol_table(&asm), stable);
+    assert_eq!(get_symbol_table(&asm, &options), stable);
 
     let asm = r#"
     (TEST)
@@ -102,60 +186,845 @@ fn test_get_symbol_table() {
     (SYMBOL)
     @10
     "#.to_string();
-    let mut stable = get_symbol_table("");
+    let mut stable = get_symbol_table("", &options);
     stable.add_entry("SYMBOL", 1);
     stable.add_entry("TEST", 0);
-    assert_eq!(get_symbol_table(&asm), stable);
+    assert_eq!(get_symbol_table(&asm, &options), stable);
+
+    let asm = r#"
+    (TEST)
+    @10
+    (TEST)
+    "#.to_string();
+    let stable = get_symbol_table(&asm, &options);
+    assert_eq!(stable.get_address("TEST"), Some(&0));
+    assert_eq!(stable.get("TEST").unwrap().definition, Some(2));
 }
 
-/// アセンブリ言語の文字列を機械語の文字列に変換する
-fn asm_to_hack(asm: String) -> Option<String> {
-    let mut stable = get_symbol_table(&asm);
+/// 現A命令が疑似命令の展開で作ったラベル（`SymbolKind::Internal`）を参照して
+/// いるかどうか
+fn is_internal(stable: &SymbolTable, parser: &Parser) -> bool {
+    parser.pseudo().is_some()
+        && stable.get(&parser.symbol()).is_some_and(|s| s.kind == SymbolKind::Internal)
+}
+
+/// 現コマンドの`@Xxx`または`(Xxx)`の`Xxx`がシンボルの文法に合っているかを
+/// 検査し、合っていない場合は問題の文字を指すエラーを返す。`@`の後ろが数字
+/// だけの場合は定数として範囲を検査する。`legacy`が`true`のときは、空白で
+/// 区切られた`( my label )`のようなシンボルだけをエラーにする
+fn check_symbol(file_name: &str, parser: &Parser, legacy: bool) -> Option<Diagnostic> {
+    let (raw, column) = parser.raw_command();
+    let body = match parser.command_type() {
+        CommandType::A => &raw[1..],
+        _ => &raw[1..raw.len()-1],
+    };
+    let column = column + 1 + body.chars().take_while(|c| c.is_whitespace()).count();
+    let symbol = body.trim();
+    let error = |column: usize, message: String| {
+        let mut error = Diagnostic::error(file_name, Some(parser.line_number()), message);
+        error.column = Some(column);
+        Some(error)
+    };
+
+    if legacy {
+        let (i, c) = symbol.chars().enumerate().find(|(_, c)| c.is_whitespace())?;
+        return error(column + i, format!("invalid character `{}` in symbol `{}`", c, symbol))
+    }
+
+    if symbol.is_empty() {
+        return error(column, format!("missing symbol in `{}`", raw))
+    }
+
+    if parser.command_type() == CommandType::A
+       && symbol.chars().all(|c| c.is_ascii_digit()) {
+        return match u16::from_str(symbol) {
+            Ok(number) if number <= 0x7fff => None,
+            _ => error(column,
+                       format!("constant `{}` is out of range (0-32767)", symbol)),
+        }
+    }
+
+    match invalid_symbol_char(symbol)? {
+        (0, c) if c.is_ascii_digit() => error(column,
+            format!("symbol `{}` must not start with a digit", symbol)),
+        (i, c) => error(column + i,
+            format!("invalid character `{}` in symbol `{}`", c, symbol)),
+    }
+}
+#[test]
+fn test_check_symbol() {
+    let asm = r#"
+    @LOOP
+    (Main.fib$ret.1)
+    @32767
+    @32768
+    @1abc
+      ( my label )
+    @a-b
+    ()
+    "#.to_string();
     let mut parser = Parser::new(asm);
-    let mut hack = String::new();
-    let mut vcount = 15; // 変数シンボルのカウンター
-    
-    // パースしてhackに代入
+    let mut results = Vec::new();
     while parser.has_more_commands() {
         parser.advance();
+        results.push(check_symbol("Test.asm", &parser, false).map(|e| (e.column, e.message)));
+    }
 
-        match parser.command_type() {
-            CommandType::None => return None,
+    assert_eq!(results, vec![
+        None,
+        None,
+        None,
+        Some((Some(6), "constant `32768` is out of range (0-32767)".to_string())),
+        Some((Some(6), "symbol `1abc` must not start with a digit".to_string())),
+        Some((Some(11), "invalid character ` ` in symbol `my label`".to_string())),
+        Some((Some(7), "invalid character `-` in symbol `a-b`".to_string())),
+        Some((Some(6), "missing symbol in `()`".to_string())),
+    ]);
+
+    // --legacy-symbolsでも空白で区切られたシンボルはエラーにする
+    let asm = "@1abc\n( my label )\n@a b".to_string();
+    let mut parser = Parser::new(asm);
+    let mut results = Vec::new();
+    while parser.has_more_commands() {
+        parser.advance();
+        results.push(check_symbol("Test.asm", &parser, true).map(|e| (e.column, e.message)));
+    }
+
+    assert_eq!(results, vec![
+        None,
+        Some((Some(5), "invalid character ` ` in symbol `my label`".to_string())),
+        Some((Some(3), "invalid character ` ` in symbol `a b`".to_string())),
+    ]);
+}
+
+/// 現C命令のdestとjumpのニーモニックの中に空白がないかを検査し、ある場合は
+/// その空白を指すエラーを返す。`=`や`;`の前後とcompの中の空白は許す
+fn check_mnemonics(file_name: &str, parser: &Parser) -> Option<Diagnostic> {
+    let (raw, column) = parser.raw_command();
+    let mut fields = Vec::new();
+    if let Some(n) = raw.find('=') {
+        fields.push(("dest", 0, &raw[..n]));
+    }
+    if let Some(n) = raw.find(';') {
+        fields.push(("jump", n + 1, &raw[n+1..]));
+    }
+
+    fields.into_iter().find_map(|(field, start, text)| {
+        let leading = text.chars().take_while(|c| c.is_whitespace()).count();
+        let monic = text.trim();
+        let (i, c) = monic.chars().enumerate().find(|(_, c)| c.is_whitespace())?;
+        let column = column + raw[..start].chars().count() + leading + i;
+        let mut error = Diagnostic::error(file_name, Some(parser.line_number()),
+            format!("invalid character `{}` in {} `{}`", c, field, monic));
+        error.column = Some(column);
+        Some(error)
+    })
+}
+#[test]
+fn test_check_mnemonics() {
+    let asm = "D = M ; JGT\nA M = D\n0 ; J M P\nD;JEQ\nAM=M+1".to_string();
+    let mut parser = Parser::new(asm);
+    let mut results = Vec::new();
+    while parser.has_more_commands() {
+        parser.advance();
+        results.push(check_mnemonics("Test.asm", &parser).map(|e| (e.column, e.message)));
+    }
+
+    assert_eq!(results, vec![
+        None,
+        Some((Some(2), "invalid character ` ` in dest `A M`".to_string())),
+        Some((Some(6), "invalid character ` ` in jump `J M P`".to_string())),
+        None,
+        None,
+    ]);
+}
+
+/// `asm`のA命令が参照しているシンボルのうち、`stable`にないものを変数として
+/// `options.variables`の順番でアドレスを割り当てる。変数とアドレスの割り当て順
+/// のリストを返す
+fn allocate_variables(file_name: &str, asm: &str, stable: &SymbolTable,
+                      options: &AssembleOptions) -> Result<Vec<(String, usize, usize)>, Diagnostic> {
+    let settings = &options.variables;
+    let mut parser = Parser::with_options(asm.to_string(), options.comments, options.stack,
+                                          options.ignore_case);
+    let mut variables: Vec<(String, usize, usize)> = Vec::new(); // 名前、参照の回数、最初の行
+
+    while parser.has_more_commands() {
+        parser.advance();
+        if parser.command_type() != CommandType::A {
+            continue;
+        }
+
+        let name = parser.symbol();
+        if i16::from_str(&name).is_ok() || stable.contains(&name) {
+            continue;
+        }
+        match variables.iter_mut().find(|(n, _, _)| stable.key(n) == stable.key(&name)) {
+            Some((_, count, _)) => *count += 1,
+            None => variables.push((name, 1, parser.line_number())),
+        }
+    }
+
+    match settings.order {
+        VariableOrder::FirstUse => (),
+        VariableOrder::Alphabetical => variables.sort_by(|a, b| a.0.cmp(&b.0)),
+        VariableOrder::Frequency => variables.sort_by_key(|(_, count, _)| std::cmp::Reverse(*count)),
+    }
+
+    for (i, (name, address)) in settings.pinned.iter().enumerate() {
+        if stable.contains(name) {
+            return Err(Diagnostic::error(file_name, None,
+                format!("`{}` can't be pinned because it is already defined", name)))
+        }
+        if let Some((other, _)) = settings.pinned[..i].iter().find(|(_, a)| a == address) {
+            return Err(Diagnostic::error(file_name, None,
+                format!("`{}` and `{}` are pinned to the same address {}", other, name, address)))
+        }
+    }
+
+    let mut next = settings.base as usize;
+    let mut allocated = Vec::new();
+    for (name, _, line) in variables {
+        if let Some((_, address)) = settings.pinned.iter()
+            .find(|(n, _)| stable.key(n) == stable.key(&name)) {
+            allocated.push((name, *address as usize, line));
+            continue;
+        }
+
+        while settings.pinned.iter().any(|(_, address)| *address as usize == next) {
+            next += 1;
+        }
+        if next > settings.limit as usize {
+            return Err(Diagnostic::error(file_name, Some(line),
+                format!("no address left for variable `{}` ({}-{})",
+                        name, settings.base, settings.limit)))
+        }
+        allocated.push((name, next, line));
+        next += 1;
+    }
+
+    Ok(allocated)
+}
+#[test]
+fn test_allocate_variables() {
+    let asm = "@b\n@a\n@LOOP\n(LOOP)\n@a\n@c\n@R0\n@5\n@c\n@c\n";
+    let mut options = AssembleOptions::default();
+    let stable = get_symbol_table(asm, &options);
+    let variables = |options: &AssembleOptions| {
+        allocate_variables("Test.asm", asm, &stable, options).map(|v| {
+            v.into_iter().map(|(n, a, _)| format!("{}={}", n, a)).collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(variables(&options).unwrap(), vec!["b=16", "a=17", "c=18"]);
+
+    options.variables.order = VariableOrder::Alphabetical;
+    assert_eq!(variables(&options).unwrap(), vec!["a=16", "b=17", "c=18"]);
+
+    options.variables.order = VariableOrder::Frequency;
+    options.variables.base = 0x100;
+    assert_eq!(variables(&options).unwrap(), vec!["c=256", "a=257", "b=258"]);
+
+    options.variables.pinned = vec![("a".to_string(), 0x100), ("x".to_string(), 0x101)];
+    assert_eq!(variables(&options).unwrap(), vec!["c=258", "a=256", "b=259"]);
+
+    options.variables.limit = 0x102;
+    assert_eq!(variables(&options), Err(Diagnostic::error("Test.asm", Some(1),
+               "no address left for variable `b` (256-258)".to_string())));
+
+    options.variables.pinned = vec![("LOOP".to_string(), 0x100)];
+    assert_eq!(variables(&options), Err(Diagnostic::error("Test.asm", None,
+               "`LOOP` can't be pinned because it is already defined".to_string())));
+    options.variables.pinned = vec![("a".to_string(), 0x100), ("b".to_string(), 0x100)];
+    assert_eq!(variables(&options), Err(Diagnostic::error("Test.asm", None,
+               "`a` and `b` are pinned to the same address 256".to_string())));
+}
+
+/// `name`がこれまでの綴り`spellings`と大文字と小文字だけ異なる場合、その綴りを
+/// 返す。新しい綴りは`spellings`に加えるので、同じ綴りについては一度しか返さない
+fn case_variant(spellings: &mut Vec<String>, name: &str) -> Option<String> {
+    if spellings.iter().any(|s| s == name) {
+        return None
+    }
+    let variant = spellings.iter().find(|s| s.eq_ignore_ascii_case(name)).cloned();
+    spellings.push(name.to_string());
+    variant
+}
+#[test]
+fn test_case_variant() {
+    let mut spellings = vec!["LOOP".to_string()];
+    assert_eq!(case_variant(&mut spellings, "LOOP"), None);
+    assert_eq!(case_variant(&mut spellings, "loop"), Some("LOOP".to_string()));
+    assert_eq!(case_variant(&mut spellings, "loop"), None);
+    assert_eq!(case_variant(&mut spellings, "i"), None);
+}
+
+/// `file_name`のアセンブリ言語の文字列をアセンブルし、各命令の機械語と
+/// ラベル・変数の一覧を返す。エラーがある場合はすべてのエラーを返す
+fn assemble(file_name: &str, asm: String, options: &AssembleOptions)
+    -> Result<Assembly, Vec<Diagnostic>> {
+    let mut stable = get_symbol_table(&asm, options);
+    let mut parser = Parser::with_options(asm.clone(), options.comments, options.stack,
+                                          options.ignore_case);
+    let mut assembly = Assembly::new(file_name);
+    let mut errors = Vec::new();
+
+    for (line, column, message) in parser.errors() {
+        let mut error = Diagnostic::error(file_name, Some(*line), message.clone());
+        error.column = Some(*column);
+        errors.push(error);
+    }
+    for (line, column, message) in parser.warnings() {
+        let mut warning = Diagnostic::warning(file_name, Some(*line), message.clone());
+        warning.column = Some(*column);
+        assembly.warnings.push(warning);
+    }
+
+    // ソースの外から定義されたシンボルの設定。ラベルとの重複はラベルの行で報告する
+    for (name, value) in &options.defines {
+        match stable.get(name).map(|s| s.kind) {
+            None | Some(SymbolKind::Label) => stable.add_entry(name, *value as usize),
+            Some(_) => errors.push(Diagnostic::error(file_name, None,
+                format!("`{}` is already defined", name))),
+        }
+    }
+
+    // 大文字と小文字だけ異なるシンボルを警告するための綴りの一覧
+    let mut spellings: Vec<String> = options.defines.iter().map(|(n, _)| n.clone()).collect();
+    if !options.no_predefined {
+        spellings.extend(PREDEFINED.iter().map(|(n, _)| n.to_string()));
+    }
+    let mut warn_case = |name: &str, line: usize, warnings: &mut Vec<Diagnostic>| {
+        if options.ignore_symbol_case {
+            return
+        }
+        if let Some(variant) = case_variant(&mut spellings, name) {
+            warnings.push(Diagnostic::warning(file_name, Some(line),
+                format!("symbol `{}` differs only in case from `{}`", name, variant)));
+        }
+    };
+
+    // 変数シンボルの割り当て
+    match allocate_variables(file_name, &asm, &stable, options) {
+        Ok(variables) => {
+            for (name, address, line) in &variables {
+                stable.add_symbol(name, *address, SymbolKind::Variable, Some(*line));
+            }
+            assembly.variables = variables.into_iter().map(|(n, a, _)| (n, a)).collect();
+        },
+        Err(error) => {
+            errors.push(error);
+            return Err(errors)
+        },
+    }
+
+    // パースしてassemblyに代入
+    while parser.has_more_commands() {
+        parser.advance();
+
+        let line = parser.line_number();
+        let address = assembly.instructions.len();
+        let kind = parser.command_type();
+        let mut symbol = None;
+        let word = match kind {
+            CommandType::None => {
+                errors.push(Diagnostic::error(file_name, Some(line),
+                    format!("unknown command `{}`", parser.command())));
+                continue;
+            },
+            CommandType::A if is_internal(&stable, &parser) => {
+                *stable.get_address(&parser.symbol()).unwrap() as u16
+            },
             CommandType::A => {
+                if let Some(error) = check_symbol(file_name, &parser, options.legacy_symbols) {
+                    errors.push(error);
+                    continue;
+                }
+
                 let mut bin = match i16::from_str(&parser.symbol()) {
                     Ok(number) => {
                         number_to_16bin(number)
                     },
                     Err(_) => {
-                        let symbol = parser.symbol();
-                        if stable.contains(&symbol) {
-                            let address = *stable.get_address(&symbol).unwrap();
-                            number_to_16bin(address as i16)
-                        } else {
-                            vcount+=1;
-                            stable.add_entry(&symbol, vcount as usize);
-                            number_to_16bin(vcount as i16)
-                        }
+                        let name = parser.symbol();
+                        warn_case(&name, line, &mut assembly.warnings);
+                        stable.add_reference(&name, line);
+                        let address = *stable.get_address(&name).unwrap();
+                        symbol = Some(name);
+                        number_to_16bin(address as i16)
                     }
                 };
 
                 bin[0] = 0; // 先頭を0にする
-                hack += &bin.iter().map(|b| b.to_string()).collect::<String>();
-                hack += "\n";
+                bin.iter().fold(0, |word, b| word << 1 | *b as u16)
             },
             CommandType::C => {
-                hack += "111";
-                hack += &Code::comp(&parser.comp()).unwrap();
-                hack += &Code::dest(&parser.dest()).unwrap();
-                hack += &Code::jump(&parser.jump()).unwrap();
+                if let Some(error) = check_mnemonics(file_name, &parser) {
+                    errors.push(error);
+                    continue;
+                }
 
-                hack += "\n";
+                let monic = |monic: String| {
+                    if options.ignore_case { monic.to_ascii_uppercase() } else { monic }
+                };
+                let (comp_monic, dest_monic, jump_monic) =
+                    (monic(parser.comp()), monic(parser.dest()), monic(parser.jump()));
+                let comp = options.isa.comp(&comp_monic);
+                let dest = options.isa.dest(&dest_monic);
+                let jump = options.isa.jump(&jump_monic);
+                if options.lint_comp {
+                    if let Some(canonical) = Code::canonical_comp(&comp_monic) {
+                        if canonical != comp_monic {
+                            assembly.warnings.push(Diagnostic::warning(file_name, Some(line),
+                                format!("comp `{}` is usually written `{}`",
+                                        comp_monic, canonical)));
+                        }
+                    }
+                }
+                for (field, monic, code) in [("comp", comp_monic, &comp),
+                                             ("dest", dest_monic, &dest),
+                                             ("jump", jump_monic, &jump)] {
+                    if code.is_none() {
+                        let duplicate = monic.chars()
+                            .find(|c| field == "dest" && monic.matches(*c).count() > 1);
+                        let message = match duplicate {
+                            Some(register) => format!("duplicate register `{}` in dest `{}`",
+                                                      register, monic),
+                            None => format!("invalid {} `{}` in `{}`",
+                                            field, monic, parser.command()),
+                        };
+                        errors.push(Diagnostic::error(file_name, Some(line), message));
+                    }
+                }
+
+                match (comp, dest, jump) {
+                    (Some(comp), Some(dest), Some(jump)) => {
+                        let bin = format!("{}{}{}", comp, dest, jump);
+                        u16::from_str_radix(&bin, 2).unwrap()
+                    },
+                    _ => continue
+                }
             },
-            CommandType::L => ()
-        }
+            CommandType::L if parser.pseudo().is_some() => continue,
+            CommandType::L => {
+                if let Some(error) = check_symbol(file_name, &parser, options.legacy_symbols) {
+                    errors.push(error);
+                    continue;
+                }
+
+                let origin = match stable.get(&parser.symbol()) {
+                    Some(s) if s.kind == SymbolKind::Predefined => {
+                        Some("as a predefined symbol".to_string())
+                    },
+                    Some(s) if s.kind == SymbolKind::Constant => {
+                        Some("by -D or --symbols".to_string())
+                    },
+                    Some(s) => s.definition.filter(|l| *l != line)
+                        .map(|first| format!("on line {}", first)),
+                    None => None,
+                };
+                if let Some(origin) = origin {
+                    errors.push(Diagnostic::error(file_name, Some(line),
+                        format!("label `{}` is already defined {}", parser.symbol(), origin)));
+                    continue;
+                }
+                warn_case(&parser.symbol(), line, &mut assembly.warnings);
+                assembly.labels.push((parser.symbol(), address));
+                continue;
+            }
+        };
+
+        assembly.instructions.push(Instruction {
+            line,
+            text: parser.command().to_string(),
+            address,
+            word,
+            kind,
+            symbol,
+            pseudo: parser.pseudo().map(|p| p.to_string()),
+        });
     }
 
-    Some(hack)
+    if !errors.is_empty() {
+        return Err(errors)
+    }
+
+    assembly.symbols = stable;
+    Ok(assembly)
+}
+#[test]
+fn test_assemble() {
+    let asm = r#"
+    @R1
+    D=M
+    (LOOP)
+    @i
+    @LOOP
+    0;JMP
+    "#.to_string();
+    let options = AssembleOptions::default();
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.file_name, "Test.asm");
+    assert_eq!(assembly.words(), vec![1, 0xfc10, 16, 2, 0xea87]);
+    assert_eq!(assembly.labels, vec![("LOOP".to_string(), 2)]);
+    assert_eq!(assembly.variables, vec![("i".to_string(), 16)]);
+    assert_eq!(assembly.instructions[3], Instruction {
+        line: 6,
+        text: "@LOOP".to_string(),
+        address: 3,
+        word: 2,
+        kind: CommandType::A,
+        symbol: Some("LOOP".to_string()),
+        pseudo: None,
+    });
+    assert_eq!(assembly.warnings, vec![]);
+
+    let asm = r#"
+    aiueo
+    D=X
+    A=M;JJJ
+    "#.to_string();
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", Some(2),
+            "unknown command `aiueo`".to_string()),
+        Diagnostic::error("Test.asm", Some(3),
+            "invalid comp `X` in `D=X`".to_string()),
+        Diagnostic::error("Test.asm", Some(4),
+            "invalid jump `JJJ` in `A=M;JJJ`".to_string()),
+    ]);
+
+    let asm = r#"
+    DM=M
+    MM=D
+    "#.to_string();
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", Some(3),
+            "duplicate register `M` in dest `MM`".to_string()),
+    ]);
+
+    let asm = "@R0 /* test\nD=M".to_string();
+    let mut error = Diagnostic::error("Test.asm", Some(1),
+        "unterminated block comment".to_string());
+    error.column = Some(5);
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![error]);
+
+    let asm = r#"
+    (1st)
+    @1st
+    "#.to_string();
+    assert_eq!(assemble("Test.asm", asm.clone(), &options).unwrap_err().len(), 2);
+    let options = AssembleOptions {
+        legacy_symbols: true,
+        ..AssembleOptions::default()
+    };
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap().words(), vec![0]);
+
+    let asm = "( my label )\n@mylabel\n".to_string();
+    let mut error = Diagnostic::error("Test.asm", Some(1),
+        "invalid character ` ` in symbol `my label`".to_string());
+    error.column = Some(5);
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![error]);
+}
+#[test]
+fn test_assemble_comp_spellings() {
+    let asm = r#"
+    D=M+D
+    M = 1 + M
+    D=D|A
+    "#.to_string();
+    let options = AssembleOptions::default();
+    let assembly = assemble("Test.asm", asm.clone(), &options).unwrap();
+    assert_eq!(assembly.words(), vec![0xf090, 0xfdc8, 0xe550]);
+    assert_eq!(assembly.warnings, vec![]);
+
+    let options = AssembleOptions {
+        lint_comp: true,
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.warnings, vec![
+        Diagnostic::warning("Test.asm", Some(2),
+            "comp `M+D` is usually written `D+M`".to_string()),
+        Diagnostic::warning("Test.asm", Some(3),
+            "comp `1+M` is usually written `M+1`".to_string()),
+    ]);
+}
+#[test]
+fn test_assemble_pseudo() {
+    let asm = r#"
+    mov i, 10
+    (LOOP)
+    if-D-le END
+    inc i
+    goto LOOP
+    (END)
+    halt
+    "#.to_string();
+    let options = AssembleOptions::default();
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.labels, vec![("LOOP".to_string(), 4), ("END".to_string(), 10)]);
+    assert_eq!(assembly.words(), vec![10, 0xec10, 16, 0xe308, 10, 0xe306, 16, 0xfdc8,
+                                      4, 0xea87, 10, 0xea87]);
+    assert_eq!(assembly.instructions[4].pseudo, Some("if-D-le END".to_string()));
+    assert_eq!(assembly.instructions[4].line, 4);
+    assert_eq!(assembly.instructions[0].text, "@10");
+
+    // 展開で作ったラベルは利用者のラベルとぶつからず、ラベルの一覧にも出ない
+    let asm = "(halt$3)\n@halt$3\nhalt\n".to_string();
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.labels, vec![("halt$3".to_string(), 0)]);
+    assert_eq!(assembly.words(), vec![0, 1, 0xea87]);
+    assert_eq!(assembly.instructions[1].symbol, None);
+    assert!(!xref::report(&assembly).contains("halt 3"));
+
+    let asm = "goto
+  goto 1st
+".to_string();
+    let errors: Vec<String> = assemble("Test.asm", asm, &options).unwrap_err()
+        .iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors, vec![
+        "Test.asm:1:1: error: `goto` expects 1 operand(s)",
+        "Test.asm:2:8: error: symbol `1st` must not start with a digit",
+    ]);
+}
+#[test]
+fn test_assemble_stack() {
+    // SP=256から3と5を積み、取り出してR5=5、R6=3にする
+    let asm = r#"
+    mov SP, 256
+    mov D, 3
+    push D
+    mov D, 5
+    push D
+    @R5
+    pop M
+    pop A
+    D=A
+    @R6
+    M=D
+    halt
+    "#.to_string();
+    // 上に伸びるスタックは256と257、下に伸びるスタックは255と254を使う
+    for (stack, slots) in [(Stack::Up, [256, 257]), (Stack::Down, [255, 254])] {
+        let options = AssembleOptions { stack, ..AssembleOptions::default() };
+        let assembly = assemble("Test.asm", asm.clone(), &options).unwrap();
+        let mut emulator = Emulator::new(assembly.words());
+        assert!(emulator.run(1000).1);
+        assert_eq!(emulator.ram[5..7], [5, 3]);
+        assert_eq!(emulator.ram[0], 256);
+        assert_eq!([emulator.ram[slots[0]], emulator.ram[slots[1]]], [3, 5]);
+    }
+}
+#[test]
+fn test_assemble_extended_alu() {
+    let asm = r#"
+    D=-D-1
+    M=D+M+1
+    AD=%ALU(1,0,0,0,0,0)
+    %ALUM(0,1,0,1,1,1);JMP
+    "#.to_string();
+    assert_eq!(assemble("Test.asm", asm.clone(), &AssembleOptions::default())
+               .unwrap_err().len(), 4);
+
+    let options = AssembleOptions {
+        isa: Isa { extended_alu: true, ..Isa::default() },
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.words(), vec![0xe350, 0xf5c8, 0xe830, 0xf5c7]);
+}
+#[test]
+fn test_assemble_extensions() {
+    let asm = r#"
+    D=M<<
+    AM=D>>;JEQ
+    "#.to_string();
+    assert_eq!(assemble("Test.asm", asm.clone(), &AssembleOptions::default())
+               .unwrap_err().len(), 2);
+
+    let options = AssembleOptions {
+        isa: Isa {
+            extended_alu: false,
+            extensions: vec![Extension::builtin("shift").unwrap()],
+            ..Isa::default()
+        },
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.words(), vec![0xb810, 0xa42a]);
+}
+#[test]
+fn test_assemble_no_predefined() {
+    let asm = r#"
+    @SP
+    @R15
+    @KBD
+    "#.to_string();
+    let options = AssembleOptions {
+        no_predefined: true,
+        defines: vec![("KBD".to_string(), 0x6001)],
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.words(), vec![16, 17, 0x6001]);
+}
+#[test]
+fn test_assemble_symbols() {
+    let asm = r#"
+    @i
+    (LOOP)
+    M=M+1
+    @LOOP
+    0;JMP
+    @SP
+    @i
+    "#.to_string();
+    let options = AssembleOptions {
+        defines: vec![("TIMER".to_string(), 0x6001)],
+        ..AssembleOptions::default()
+    };
+    let symbols = assemble("Test.asm", asm, &options).unwrap().symbols;
+    let summary = |name: &str| {
+        let symbol = symbols.get(name).unwrap();
+        (symbol.kind, symbol.address, symbol.definition, symbol.references.clone())
+    };
+    assert_eq!(summary("i"), (SymbolKind::Variable, 16, Some(2), vec![2, 8]));
+    assert_eq!(summary("LOOP"), (SymbolKind::Label, 1, Some(3), vec![5]));
+    assert_eq!(summary("SP"), (SymbolKind::Predefined, 0, None, vec![7]));
+    assert_eq!(summary("TIMER"), (SymbolKind::Constant, 0x6001, None, vec![]));
+}
+#[test]
+fn test_assemble_duplicate_labels() {
+    let asm = r#"
+    (LOOP)
+    @LOOP
+    0;JMP
+    (LOOP)
+    (Loop)
+    "#.to_string();
+    let options = AssembleOptions::default();
+    assert_eq!(assemble("Test.asm", asm.clone(), &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", Some(5),
+            "label `LOOP` is already defined on line 2".to_string()),
+    ]);
+
+    let options = AssembleOptions {
+        ignore_symbol_case: true,
+        ..AssembleOptions::default()
+    };
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", Some(5),
+            "label `LOOP` is already defined on line 2".to_string()),
+        Diagnostic::error("Test.asm", Some(6),
+            "label `Loop` is already defined on line 2".to_string()),
+    ]);
+
+    // 定義済みシンボルや`-D`の定数と同じ名前のラベルも重複として報告する
+    let asm = r#"
+    (R0)
+    @X
+    (X)
+    "#.to_string();
+    let options = AssembleOptions {
+        defines: vec![("X".to_string(), 5)],
+        ..AssembleOptions::default()
+    };
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", Some(2),
+            "label `R0` is already defined as a predefined symbol".to_string()),
+        Diagnostic::error("Test.asm", Some(4),
+            "label `X` is already defined by -D or --symbols".to_string()),
+    ]);
+}
+#[test]
+fn test_assemble_ignore_case() {
+    let asm = r#"
+    (Loop)
+    @i
+    d=m
+    @I
+    M=D
+    @loop
+    0;jmp
+    @sp
+    "#.to_string();
+    let options = AssembleOptions::default();
+    let errors = assemble("Test.asm", asm.clone(), &options).unwrap_err();
+    assert_eq!(errors, vec![
+        Diagnostic::error("Test.asm", Some(4), "invalid comp `m` in `d=m`".to_string()),
+        Diagnostic::error("Test.asm", Some(4), "invalid dest `d` in `d=m`".to_string()),
+        Diagnostic::error("Test.asm", Some(8), "invalid jump `jmp` in `0;jmp`".to_string()),
+    ]);
+
+    let options = AssembleOptions {
+        ignore_case: true,
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm.clone(), &options).unwrap();
+    assert_eq!(assembly.words(), vec![16, 0xfc10, 17, 0xe308, 18, 0xea87, 19]);
+    assert_eq!(assembly.warnings, vec![
+        Diagnostic::warning("Test.asm", Some(5),
+            "symbol `I` differs only in case from `i`".to_string()),
+        Diagnostic::warning("Test.asm", Some(7),
+            "symbol `loop` differs only in case from `Loop`".to_string()),
+        Diagnostic::warning("Test.asm", Some(9),
+            "symbol `sp` differs only in case from `SP`".to_string()),
+    ]);
+
+    let options = AssembleOptions {
+        ignore_case: true,
+        ignore_symbol_case: true,
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.words(), vec![16, 0xfc10, 16, 0xe308, 0, 0xea87, 0]);
+    assert_eq!(assembly.warnings, vec![]);
+    assert_eq!(assembly.variables, vec![("i".to_string(), 16)]);
+
+    // `;`のコメントと組み合わせても`;jmp`はjumpで、疑似命令とレジスタも小文字で書ける
+    let asm = r#"
+    (END)
+    0;jmp ; forever
+    push d
+    GOTO END
+    "#.to_string();
+    let options = AssembleOptions {
+        ignore_case: true,
+        comments: parser::CommentSyntax { block: true, semicolon: true },
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm, &options).unwrap();
+    assert_eq!(assembly.words(), vec![0xea87, 0, 0xfdc8, 0xfca0, 0xe308, 0, 0xea87]);
+}
+#[test]
+fn test_assemble_defines() {
+    let asm = r#"
+    @TIMER
+    D=M
+    @TIMER
+    "#.to_string();
+    let options = AssembleOptions {
+        defines: vec![("TIMER".to_string(), 0x6001)],
+        ..AssembleOptions::default()
+    };
+    let assembly = assemble("Test.asm", asm.clone(), &options).unwrap();
+    assert_eq!(assembly.words(), vec![0x6001, 0xfc10, 0x6001]);
+    assert_eq!(assembly.variables, vec![]);
+
+    let options = AssembleOptions {
+        defines: vec![("SP".to_string(), 1)],
+        ..AssembleOptions::default()
+    };
+    assert_eq!(assemble("Test.asm", asm, &options).unwrap_err(), vec![
+        Diagnostic::error("Test.asm", None, "`SP` is already defined".to_string()),
+    ]);
+}
+
+/// アセンブリ言語の文字列を機械語の文字列に変換する
+#[cfg(test)]
+fn asm_to_hack(asm: String) -> Option<String> {
+    assemble("", asm, &AssembleOptions::default())
+        .map(|assembly| Output::hack(&assembly.words()))
+        .ok()
 }
 #[test]
 fn test_asm_to_hack() {
@@ -222,54 +1091,526 @@ fn test_asm_to_hack() {
     assert_eq!(&asm_to_hack(asm).unwrap(), 
                concat!("0000", "0000", "0000", "0001", "\n",
                        "0000", "0000", "0001", "0000", "\n"));
+
+    let asm = "\t@R0\r\n\tD = M ; JGT\r\n".to_string();
+    assert_eq!(&asm_to_hack(asm).unwrap(), 
+               concat!("0000", "0000", "0000", "0000", "\n",
+                       "1111", "1100", "0001", "0001", "\n"));
 }
 
-fn main() {
-    let file_name = match env::args().nth(1) {
-        Some(file_name) => file_name,
-        None => {
-            println!("Error: File name is not exist.");
-            print_usage();
-            return;
+/// 1つの入力ファイルに対する処理
+/// * `input`は入力ファイル名（`-`は標準入力）
+/// * `output`は出力ファイル名（`-`は標準出力）
+#[derive(Debug, PartialEq)]
+struct Job {
+    input: String,
+    output: String,
+}
+
+/// `Job`の結果。`code`は終了コード
+struct JobResult {
+    diagnostics: Vec<Diagnostic>,
+    code: i32,
+}
+
+/// `cli`の設定でアセンブルし、エラーと警告を`diagnostics`に加える。失敗した
+/// 場合は終了コードを返す
+fn assemble_source(cli: &Cli, file_name: &str, asm: String,
+                   diagnostics: &mut Vec<Diagnostic>) -> Result<Assembly, i32> {
+    let options = AssembleOptions {
+        defines: cli.defines.clone(),
+        no_predefined: cli.no_predefined,
+        comments: cli.comments,
+        legacy_symbols: cli.legacy_symbols,
+        lint_comp: cli.lint_comp,
+        ignore_case: cli.ignore_case,
+        ignore_symbol_case: cli.ignore_symbol_case,
+        isa: cli.isa.clone(),
+        stack: cli.stack,
+        variables: cli.variables.clone(),
+    };
+
+    let assembly = match assemble(file_name, asm, &options) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            diagnostics.extend(errors);
+            return Err(EXIT_DATA)
         }
     };
 
-    let output_file_name = match env::args().nth(2) {
-        Some(file_name) => file_name,
-        None => {
-            println!("Error: Output file name is not exist.");
-            print_usage();
-            return;
+    match cli.warnings {
+        Warnings::On => diagnostics.extend(assembly.warnings.iter().cloned()),
+        Warnings::Off => (),
+        Warnings::Error => {
+            if !assembly.warnings.is_empty() {
+                diagnostics.extend(assembly.warnings.iter()
+                    .map(|w| Diagnostic { severity: Severity::Error, ..w.clone() }));
+                return Err(EXIT_DATA)
+            }
         }
+    }
+
+    Ok(assembly)
+}
+
+/// `cli`のサブコマンドを実行し、`output_file_name`に書く文字列を返す。
+/// エラーと警告は`diagnostics`に加え、失敗した場合は終了コードを返す
+fn execute(cli: &Cli, file_name: &str, output_file_name: &str, source: String,
+           diagnostics: &mut Vec<Diagnostic>) -> Result<Option<String>, i32> {
+    let mut fail = |errors: Vec<Diagnostic>| {
+        diagnostics.extend(errors);
+        EXIT_DATA
     };
 
-    let mut file = match File::open(&file_name) {
-        Ok(file) => file,
-        Err(_) => {
-            println!("Error: {} is not exist.", &file_name);
-            print_usage();
-            return;
+    let output = match cli.subcommand {
+        Subcommand::Assemble => {
+            let assembly = assemble_source(cli, file_name, source, diagnostics)?;
+            let format = cli.format
+                .or_else(|| Format::from_file_name(output_file_name))
+                .unwrap_or(Format::Hack);
+            if cli.rom.is_set() && !format.is_rom() {
+                diagnostics.push(Diagnostic::error(file_name, None,
+                    "--depth and --fill can only be used with coe and mif".to_string()));
+                return Err(EXIT_USAGE)
+            }
+            match Output::generate(format, &assembly, &cli.rom) {
+                Some(output) => output,
+                None => {
+                    diagnostics.push(Diagnostic::error(file_name, None,
+                        "the program does not fit in the ROM depth".to_string()));
+                    return Err(EXIT_DATA)
+                }
+            }
+        },
+        Subcommand::Check => {
+            assemble_source(cli, file_name, source, diagnostics)?;
+            return Ok(None)
+        },
+        Subcommand::Disassemble => {
+            let words = disassembler::read_hack(file_name, &source).map_err(&mut fail)?;
+            disassembler::disassemble(file_name, &words, &cli.isa).map_err(&mut fail)?
+        },
+        Subcommand::Run => {
+            let words = if Format::from_file_name(file_name) == Some(Format::Hack) {
+                disassembler::read_hack(file_name, &source).map_err(&mut fail)?
+            } else {
+                assemble_source(cli, file_name, source, diagnostics)?.words()
+            };
+
+            let mut emulator = Emulator::with_isa(words, cli.isa.clone());
+            let (count, halted) = emulator.run(cli.cycles);
+            let mut output = if halted {
+                format!("Halted after {} cycles.\n", count)
+            } else {
+                format!("Stopped after {} cycles without halting.\n", count)
+            };
+            output += &emulator.state();
+            output
+        },
+        Subcommand::Fmt => formatter::format(&source, cli.comments, cli.ignore_case),
+        Subcommand::Xref => xref::report(&assemble_source(cli, file_name, source, diagnostics)?),
+    };
+
+    Ok(Some(output))
+}
+/// テスト用に`args`を解析した`Cli`を返す
+#[cfg(test)]
+fn parse_cli(args: &[&str]) -> Cli {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    match cli::parse(&args) {
+        Ok(Action::Execute(cli)) => cli,
+        _ => panic!(),
+    }
+}
+#[test]
+fn test_execute() {
+    let mut diagnostics = Vec::new();
+
+    // 出力は最後の改行を残す
+    let cli = parse_cli(&["Add.asm"]);
+    assert_eq!(execute(&cli, "Add.asm", "Add.hack", "@2\n".to_string(), &mut diagnostics),
+               Ok(Some("0000000000000010\n".to_string())));
+
+    // 出力ファイル名から推測した形式でも`--depth`はcoeとmifに限る
+    let cli = parse_cli(&["Add.asm", "Add.mif", "--depth", "4"]);
+    assert!(execute(&cli, "Add.asm", "Add.mif", "@2\n".to_string(), &mut diagnostics)
+            .unwrap().unwrap().contains("DEPTH=4;\n"));
+    let cli = parse_cli(&["Add.asm", "Add.json", "--depth", "4"]);
+    assert_eq!(execute(&cli, "Add.asm", "Add.json", "@2\n".to_string(), &mut diagnostics),
+               Err(EXIT_USAGE));
+    assert_eq!(diagnostics.len(), 1);
+}
+
+/// `job`の入力を読み、サブコマンドを実行して出力を書く
+fn run_job(cli: &Cli, job: &Job) -> JobResult {
+    let mut diagnostics = Vec::new();
+    let source = match read_input(&job.input) {
+        Ok(source) => source,
+        Err(error) => {
+            diagnostics.push(Diagnostic::error(&job.input, None,
+                format!("can't read the file: {}", error)));
+            return JobResult { diagnostics, code: EXIT_NO_INPUT }
         }
     };
 
-    let mut asm = String::new();
-    let _ = file.read_to_string(&mut asm);
-    let hack = match asm_to_hack(asm) {
-        Some(hack) => hack,
-        None => {
-            println!("Error: Can't parse");
-            return print_usage();
+    let output = match execute(cli, &job.input, &job.output, source, &mut diagnostics) {
+        Ok(Some(output)) => output,
+        Ok(None) => return JobResult { diagnostics, code: EXIT_OK },
+        Err(code) => return JobResult { diagnostics, code },
+    };
+
+    if let Some(parent) = Path::new(&job.output).parent() {
+        if cli.out_dir.is_some() && fs::create_dir_all(parent).is_err() {
+            diagnostics.push(Diagnostic::error(&job.output, None,
+                "can't create the output directory".to_string()));
+            return JobResult { diagnostics, code: EXIT_CANT_CREATE }
+        }
+    }
+
+    if let Err(error) = write_output(&job.output, &output) {
+        diagnostics.push(Diagnostic::error(&job.output, None,
+            format!("can't write the file: {}", error)));
+        return JobResult { diagnostics, code: EXIT_CANT_CREATE }
+    }
+
+    JobResult { diagnostics, code: EXIT_OK }
+}
+
+/// `dir`以下のすべての`.asm`ファイルを名前順に`files`に加える
+fn find_asm_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            find_asm_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "asm") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+#[test]
+fn test_find_asm_files() {
+    let dir = env::temp_dir().join(format!("find_asm_files_{}", process::id()));
+    fs::create_dir_all(dir.join("b")).unwrap();
+    for file in ["b/Max.asm", "Add.asm", "Add.hack", "b/README"] {
+        File::create(dir.join(file)).unwrap();
+    }
+
+    let mut files = Vec::new();
+    find_asm_files(&dir, &mut files).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, vec![dir.join("Add.asm"), dir.join("b/Max.asm")]);
+}
+
+/// `cli`の入力から`Job`の一覧を作る。ディレクトリはその中の`.asm`ファイルに
+/// 展開し、`--out-dir`がある場合は出力をその下に同じ構成で置く
+fn jobs(cli: &Cli) -> Result<Vec<Job>, (String, i32)> {
+    let mut inputs = Vec::new();
+    for input in &cli.inputs {
+        let path = Path::new(input);
+        if input != "-" && path.is_dir() {
+            let mut files = Vec::new();
+            if let Err(error) = find_asm_files(path, &mut files) {
+                return Err((format!("Can't read {}: {}", input, error), EXIT_NO_INPUT))
+            }
+            for file in files {
+                let relative = file.strip_prefix(path).unwrap().to_path_buf();
+                inputs.push((file, relative));
+            }
+        } else {
+            let relative = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+            inputs.push((path.to_path_buf(), relative));
+        }
+    }
+
+    if inputs.len() > 1 && cli.output.is_some() {
+        return Err(("-o can't be used with several inputs; use --out-dir".to_string(),
+                    EXIT_USAGE))
+    }
+
+    let jobs = inputs.into_iter().map(|(input, relative)| {
+        let input = input.to_string_lossy().to_string();
+        let output = match (&cli.output, &cli.out_dir) {
+            (Some(output), _) => output.to_string(),
+            (None, Some(dir)) if input != "-" => {
+                let extension = match cli.subcommand {
+                    Subcommand::Fmt | Subcommand::Disassemble => "asm",
+                    _ => cli.format.unwrap_or(Format::Hack).extension(),
+                };
+                let output = Path::new(dir).join(relative).with_extension(extension);
+                output.to_string_lossy().to_string()
+            },
+            (None, _) if cli.subcommand == Subcommand::Assemble => {
+                default_output_file_name(&input, cli.format.unwrap_or(Format::Hack))
+            },
+            (None, _) => "-".to_string(),
+        };
+
+        Job { input, output }
+    }).collect::<Vec<Job>>();
+
+    // 同じファイルに書く入力があると先の結果が失われる
+    for (i, job) in jobs.iter().enumerate().filter(|(_, job)| job.output != "-") {
+        if let Some(first) = jobs[..i].iter().find(|first| first.output == job.output) {
+            return Err((format!("{} and {} would both be written to {}",
+                                first.input, job.input, job.output), EXIT_USAGE))
+        }
+    }
+
+    Ok(jobs)
+}
+#[test]
+fn test_jobs() {
+    let cli = parse_cli(&["a/Add.asm", "Max.asm", "--out-dir", "out"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "a/Add.asm".to_string(), output: "out/Add.hack".to_string() },
+        Job { input: "Max.asm".to_string(), output: "out/Max.hack".to_string() },
+    ]);
+
+    let cli = parse_cli(&["a/Add.asm", "Max.asm", "--out-dir", "out", "-f", "coe"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "a/Add.asm".to_string(), output: "out/Add.coe".to_string() },
+        Job { input: "Max.asm".to_string(), output: "out/Max.coe".to_string() },
+    ]);
+
+    let cli = parse_cli(&["fmt", "a/Add.asm", "--out-dir", "fo"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "a/Add.asm".to_string(), output: "fo/Add.asm".to_string() },
+    ]);
+
+    // fmtは標準出力へ書き、-oで入力のファイルを上書きできる
+    let cli = parse_cli(&["fmt", "Add.asm"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "Add.asm".to_string(), output: "-".to_string() },
+    ]);
+    let cli = parse_cli(&["fmt", "Add.asm", "-o", "Add.asm"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "Add.asm".to_string(), output: "Add.asm".to_string() },
+    ]);
+
+    let cli = parse_cli(&["check", "a/Add.asm", "Max.asm"]);
+    assert_eq!(jobs(&cli).unwrap(), vec![
+        Job { input: "a/Add.asm".to_string(), output: "-".to_string() },
+        Job { input: "Max.asm".to_string(), output: "-".to_string() },
+    ]);
+
+    let cli = parse_cli(&["x/Add.asm", "y/Add.asm", "--out-dir", "out"]);
+    assert_eq!(jobs(&cli), Err(("x/Add.asm and y/Add.asm would both be written to out/Add.hack"
+                                .to_string(), EXIT_USAGE)));
+}
+
+/// `jobs`をスレッドで並列に実行し、`jobs`と同じ順に結果を返す
+fn run_jobs(cli: &Cli, jobs: &[Job]) -> Vec<JobResult> {
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut results: Vec<(usize, JobResult)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(jobs.len())).map(|_| {
+            scope.spawn(|| {
+                let mut results = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= jobs.len() {
+                        return results
+                    }
+                    results.push((i, run_job(cli, &jobs[i])));
+                }
+            })
+        }).collect();
+
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// `cli`の入力をすべて処理し、エラーと警告と結果の一覧を表示する。
+/// 終了コードを返す
+fn run_once(cli: &Cli) -> i32 {
+    let jobs = match jobs(cli) {
+        Ok(jobs) => jobs,
+        Err((message, code)) => {
+            eprintln!("Error: {}", message);
+            return code
         }
     };
-    
-    let mut output_file = match File::create(&output_file_name) {
-        Ok(file) => file,
-        Err(_) => {
-            println!("Error: {} is not exist.", &file_name);
-            print_usage();
+
+    let results = run_jobs(cli, &jobs);
+    for result in &results {
+        for diagnostic in &result.diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+    }
+
+    // 複数のファイルを処理した場合は結果の一覧を表示する
+    let failed: Vec<_> = jobs.iter().zip(&results)
+        .filter(|(_, result)| result.code != EXIT_OK)
+        .collect();
+    if jobs.len() > 1 {
+        eprintln!("{} succeeded, {} failed", jobs.len() - failed.len(), failed.len());
+        for (job, _) in &failed {
+            eprintln!("  failed: {}", job.input);
+        }
+    }
+
+    match failed.first() {
+        Some((_, result)) => result.code,
+        None => EXIT_OK,
+    }
+}
+
+/// 入力ファイルと`--symbols`、`--isa`のファイルの一覧とそれぞれの更新日時を
+/// 返す。ディレクトリは毎回展開するので、ファイルの追加と削除も変更として扱う
+fn snapshot(cli: &Cli) -> Vec<(String, Option<SystemTime>)> {
+    let inputs = jobs(cli).unwrap_or_default().into_iter().map(|job| job.input);
+    let files = cli.symbol_files.iter().chain(&cli.isa_file).cloned();
+    inputs.chain(files).map(|file_name| {
+        let modified = fs::metadata(&file_name).and_then(|m| m.modified()).ok();
+        (file_name, modified)
+    }).collect()
+}
+#[test]
+fn test_snapshot() {
+    let file_name = env::temp_dir().join(format!("snapshot_{}.asm", process::id()));
+    let file = File::create(&file_name).unwrap();
+    let cli = parse_cli(&["check", &file_name.to_string_lossy()]);
+
+    let before = snapshot(&cli);
+    assert_eq!(before, snapshot(&cli));
+    file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+    assert_ne!(before, snapshot(&cli));
+    fs::remove_file(&file_name).unwrap();
+    assert_eq!(snapshot(&cli)[0].1, None);
+
+    // --symbolsと--isaのファイルも監視する
+    let cli = parse_cli(&["check", "Add.asm", "--symbols", "hack.sym", "--isa", "cpu.toml"]);
+    let files: Vec<String> = snapshot(&cli).into_iter().map(|(file, _)| file).collect();
+    assert_eq!(files, vec!["Add.asm", "hack.sym", "cpu.toml"]);
+}
+
+/// 入力を一定の間隔で確認し、変更があるたびに`--symbols`と`--isa`のファイルを
+/// 読み直して`run_once`を実行する。出力はアセンブルが成功したときだけ書き換えられる
+fn watch(mut cli: Cli) -> ! {
+    let isa = cli.isa.clone();
+    let defines = cli.defines.clone();
+    let mut last = Vec::new();
+    loop {
+        let current = snapshot(&cli);
+        if current != last {
+            eprintln!("[watch] running {} file(s)", current.len());
+            cli.isa = isa.clone();
+            cli.defines = defines.clone();
+            let code = match load_isa(&mut cli).and_then(|_| load_symbols(&mut cli)) {
+                Ok(()) => run_once(&cli),
+                Err(code) => code,
+            };
+            match code {
+                EXIT_OK => eprintln!("[watch] ok"),
+                _ => eprintln!("[watch] failed"),
+            }
+            last = current;
+        }
+
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// `--isa`の対応表を読み込み、拡張との衝突がないか確認する。失敗した場合は
+/// エラーを表示して終了コードを返す
+fn load_isa(cli: &mut Cli) -> Result<(), i32> {
+    if let Some(file_name) = &cli.isa_file {
+        let text = match fs::read_to_string(file_name) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("{}", Diagnostic::error(file_name, None,
+                    format!("can't read the file: {}", error)));
+                return Err(EXIT_NO_INPUT)
+            }
+        };
+        match IsaTable::load(file_name, &text) {
+            Ok(table) => cli.isa.table = table,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                return Err(EXIT_DATA)
+            }
+        }
+    }
+
+    let conflicts = cli.isa.conflicts();
+    for conflict in &conflicts {
+        eprintln!("Error: {}", conflict);
+    }
+    if !conflicts.is_empty() {
+        return Err(EXIT_USAGE)
+    }
+
+    Ok(())
+}
+
+/// `--symbols`のファイルを読み込み、定義されたシンボルを`cli.defines`に
+/// 加える。失敗した場合はエラーを表示して終了コードを返す
+fn load_symbols(cli: &mut Cli) -> Result<(), i32> {
+    for file_name in &cli.symbol_files {
+        let text = match fs::read_to_string(file_name) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("{}", Diagnostic::error(file_name, None,
+                    format!("can't read the file: {}", error)));
+                return Err(EXIT_NO_INPUT)
+            }
+        };
+        match cli::parse_symbol_file(file_name, &text) {
+            Ok(symbols) => cli.defines.extend(symbols),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                return Err(EXIT_DATA)
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut cli = match cli::parse(&args) {
+        Ok(Action::Execute(cli)) => cli,
+        Ok(Action::Help) => {
+            print!("{}", cli::usage());
             return;
+        },
+        Ok(Action::Version) => {
+            println!("assembler {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Err(message) => {
+            eprintln!("Error: {}", message);
+            eprint!("{}", cli::usage());
+            process::exit(EXIT_USAGE);
         }
     };
 
-    let _ = output_file.write(hack.trim().as_bytes());
-}
\ No newline at end of file
+    if cli.watch {
+        if cli.inputs.iter().any(|input| input == "-") {
+            eprintln!("Error: --watch can't read from stdin");
+            process::exit(EXIT_USAGE);
+        }
+        watch(cli);
+    }
+
+    if let Err(code) = load_isa(&mut cli).and_then(|_| load_symbols(&mut cli)) {
+        process::exit(code);
+    }
+
+    process::exit(run_once(&cli));
+}
@@ -0,0 +1,495 @@
+// 機械語の出力形式
+
+use crate::assembly::Assembly;
+use crate::parser::CommandType;
+
+/// 機械語のワード列を書き出す形式
+/// * `Format::Hack`は1行に1命令の2進数の文字列（`.hack`）
+/// * `Format::Logisim`はLogisim Evolutionの`v2.0 raw`イメージ
+/// * `Format::Digital`はhneemann's Digitalの`.hex`ファイル
+/// * `Format::Coe`はXilinxのブロックRAM初期化用の`.coe`ファイル
+/// * `Format::Mif`はIntel(Altera)のメモリ初期化用の`.mif`ファイル
+/// * `Format::Json`は各命令とシンボルの情報を含むJSON
+/// * `Format::Listing`はソースの行番号、アドレス、機械語、命令を並べた表
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Hack,
+    Logisim,
+    Digital,
+    Coe,
+    Mif,
+    Json,
+    Listing,
+}
+
+impl Format {
+    /// 名前から出力形式を返す
+    pub fn from_name(name: &str) -> Option<Format> {
+        let format = match name {
+            "hack" => Format::Hack,
+            "logisim" => Format::Logisim,
+            "digital" => Format::Digital,
+            "coe" => Format::Coe,
+            "mif" => Format::Mif,
+            "json" => Format::Json,
+            "listing" => Format::Listing,
+            _ => return None
+        };
+
+        Some(format)
+    }
+
+    /// 出力形式のファイルの拡張子
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Hack => "hack",
+            Format::Logisim => "img",
+            Format::Digital => "hex",
+            Format::Coe => "coe",
+            Format::Mif => "mif",
+            Format::Json => "json",
+            Format::Listing => "lst",
+        }
+    }
+
+    /// ROMの設定（`RomOptions`）を使う形式なら`true`を返す
+    pub fn is_rom(self) -> bool {
+        matches!(self, Format::Coe | Format::Mif)
+    }
+
+    /// ファイル名の拡張子から出力形式を推測する
+    pub fn from_file_name(file_name: &str) -> Option<Format> {
+        let extension = file_name.rsplit_once('.')?.1;
+        match extension {
+            "hack" => Some(Format::Hack),
+            "img" => Some(Format::Logisim),
+            "hex" => Some(Format::Digital),
+            "coe" => Some(Format::Coe),
+            "mif" => Some(Format::Mif),
+            "json" => Some(Format::Json),
+            "lst" => Some(Format::Listing),
+            _ => None
+        }
+    }
+}
+
+/// ROMの初期化ファイルの設定
+/// * `depth`はROMのワード数。`None`のときはプログラムの長さ（最低1ワード）になる
+/// * `fill`はプログラムの後ろの未使用のワードを埋める値。`None`のときは0
+#[derive(Debug, PartialEq, Default)]
+pub struct RomOptions {
+    pub depth: Option<usize>,
+    pub fill: Option<u16>,
+}
+
+impl RomOptions {
+    /// `depth`か`fill`が指定されていれば`true`を返す
+    pub fn is_set(&self) -> bool {
+        self.depth.is_some() || self.fill.is_some()
+    }
+
+    /// `words`を書き込むROMのワード数を返す。`words`が収まらない場合は`None`
+    fn depth(&self, words: &[u16]) -> Option<usize> {
+        match self.depth {
+            Some(depth) if depth < words.len() => None,
+            Some(depth) => Some(depth),
+            None => Some(words.len().max(1)),
+        }
+    }
+
+    /// 未使用のワードを埋める値を返す
+    fn fill(&self) -> u16 {
+        self.fill.unwrap_or(0)
+    }
+}
+
+/// 機械語のワード列を各形式の文字列へ変換する
+pub struct Output {}
+
+impl Output {
+    /// `format`の形式で`assembly`を文字列にする。機械語が`options`のROMに
+    /// 収まらない場合は`None`を返す
+    pub fn generate(format: Format, assembly: &Assembly, options: &RomOptions)
+        -> Option<String> {
+        let words = &assembly.words();
+        match format {
+            Format::Hack => Some(Output::hack(words)),
+            Format::Logisim => Some(Output::logisim(words)),
+            Format::Digital => Some(Output::digital(words)),
+            Format::Coe => Output::coe(words, options),
+            Format::Mif => Output::mif(words, options),
+            Format::Json => Some(Output::json(assembly)),
+            Format::Listing => Some(Output::listing(assembly)),
+        }
+    }
+
+    /// 1行に1命令ずつ16桁の2進数で書き出す
+    pub fn hack(words: &[u16]) -> String {
+        words.iter().map(|word| format!("{:016b}\n", word)).collect()
+    }
+
+    /// Logisimの`v2.0 raw`形式で書き出す。同じ値が3つ以上続く場合は`5*0`の
+    /// ように連長圧縮する
+    pub fn logisim(words: &[u16]) -> String {
+        let mut entries = Vec::new();
+        let mut i = 0;
+
+        while i < words.len() {
+            let run = words[i..].iter().take_while(|w| **w == words[i]).count();
+            if run >= 3 {
+                entries.push(format!("{}*{:x}", run, words[i]));
+            } else {
+                for _ in 0..run {
+                    entries.push(format!("{:x}", words[i]));
+                }
+            }
+            i += run;
+        }
+
+        let mut image = "v2.0 raw\n".to_string();
+        for line in entries.chunks(8) {
+            image += &line.join(" ");
+            image += "\n";
+        }
+
+        image
+    }
+
+    /// Digitalの`.hex`形式で書き出す。1行に1ワードずつ16進数で並べる
+    pub fn digital(words: &[u16]) -> String {
+        let mut image = "v2.0 raw\n".to_string();
+        for word in words {
+            image += &format!("{:x}\n", word);
+        }
+
+        image
+    }
+
+    /// Xilinxの`.coe`形式で書き出す。ROMの残りは`options.fill`で埋める
+    pub fn coe(words: &[u16], options: &RomOptions) -> Option<String> {
+        let depth = options.depth(words)?;
+        let vector: Vec<_> = (0..depth)
+            .map(|i| format!("{:04x}", words.get(i).copied().unwrap_or(options.fill())))
+            .collect();
+
+        let mut coe = "memory_initialization_radix=16;\n".to_string();
+        coe += "memory_initialization_vector=\n";
+        coe += &vector.join(",\n");
+        coe += ";\n";
+
+        Some(coe)
+    }
+
+    /// Intelの`.mif`形式で書き出す。ROMの残りは`options.fill`で埋める
+    pub fn mif(words: &[u16], options: &RomOptions) -> Option<String> {
+        let depth = options.depth(words)?;
+        let mut mif = String::new();
+
+        mif += "WIDTH=16;\n";
+        mif += &format!("DEPTH={};\n\n", depth);
+        mif += "ADDRESS_RADIX=UNS;\n";
+        mif += "DATA_RADIX=HEX;\n\n";
+        mif += "CONTENT BEGIN\n";
+        for (address, word) in words.iter().enumerate() {
+            mif += &format!("\t{} : {:04x};\n", address, word);
+        }
+        match depth - words.len() {
+            0 => (),
+            1 => mif += &format!("\t{} : {:04x};\n", words.len(), options.fill()),
+            _ => mif += &format!("\t[{}..{}] : {:04x};\n",
+                                 words.len(), depth - 1, options.fill()),
+        }
+        mif += "END;\n";
+
+        Some(mif)
+    }
+
+    /// 各命令の情報とラベル・変数の一覧をJSONで書き出す
+    pub fn json(assembly: &Assembly) -> String {
+        let file = json_string(&assembly.file_name);
+        let instructions: Vec<_> = assembly.instructions.iter().map(|i| {
+            let kind = match i.kind {
+                CommandType::A => "A",
+                CommandType::C => "C",
+                CommandType::L => "L",
+                CommandType::None => "None",
+            };
+            let symbol = match &i.symbol {
+                Some(symbol) => json_string(symbol),
+                None => "null".to_string(),
+            };
+            let pseudo = match &i.pseudo {
+                Some(pseudo) => json_string(pseudo),
+                None => "null".to_string(),
+            };
+
+            format!(concat!("    {{\"file\": {}, \"line\": {}, \"text\": {}, ",
+                            "\"address\": {}, \"word\": {}, ",
+                            "\"binary\": \"{:016b}\", \"kind\": \"{}\", ",
+                            "\"symbol\": {}, \"pseudo\": {}}}"),
+                    file, i.line, json_string(&i.text), i.address, i.word,
+                    i.word, kind, symbol, pseudo)
+        }).collect();
+
+        let mut json = "{\n".to_string();
+        json += &format!("  \"file\": {},\n", file);
+        json += "  \"instructions\": [";
+        if !instructions.is_empty() {
+            json += &format!("\n{}\n  ", instructions.join(",\n"));
+        }
+        json += "],\n";
+        json += &format!("  \"labels\": {},\n", json_table(&assembly.labels));
+        json += &format!("  \"variables\": {}\n", json_table(&assembly.variables));
+        json += "}\n";
+
+        json
+    }
+
+    /// 行番号、アドレス、機械語、命令を1行ずつ並べたリストを書き出す。疑似命令
+    /// はその行の後ろに展開した命令を字下げして並べる
+    pub fn listing(assembly: &Assembly) -> String {
+        let mut listing = String::new();
+        let mut last_line = 0;
+
+        for i in &assembly.instructions {
+            let text = match &i.pseudo {
+                Some(pseudo) => {
+                    if i.line != last_line {
+                        listing += &format!("{:>5}{:26}{}\n", i.line, "", pseudo);
+                    }
+                    format!("  {}", i.text)
+                },
+                None => i.text.clone(),
+            };
+            listing += &format!("{:>5} {:>5}  {:016b}  {}\n", i.line, i.address, i.word, text);
+            last_line = i.line;
+        }
+
+        listing
+    }
+}
+
+/// 文字列をJSONの文字列リテラルにする
+fn json_string(s: &str) -> String {
+    let mut json = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\r' => json += "\\r",
+            '\t' => json += "\\t",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+/// シンボルとアドレスのリストをJSONのオブジェクトにする
+fn json_table(table: &[(String, usize)]) -> String {
+    let entries: Vec<_> = table.iter()
+        .map(|(symbol, address)| format!("{}: {}", json_string(symbol), address))
+        .collect();
+
+    format!("{{{}}}", entries.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Format, Output, RomOptions, json_string};
+    use crate::assembly::{Assembly, Instruction};
+    use crate::parser::CommandType;
+
+    #[test]
+    fn test_format_from_name() {
+        assert_eq!(Format::from_name("hack"), Some(Format::Hack));
+        assert_eq!(Format::from_name("logisim"), Some(Format::Logisim));
+        assert_eq!(Format::from_name("digital"), Some(Format::Digital));
+        assert_eq!(Format::from_name("coe"), Some(Format::Coe));
+        assert_eq!(Format::from_name("mif"), Some(Format::Mif));
+        assert_eq!(Format::from_name("json"), Some(Format::Json));
+        assert_eq!(Format::from_name("a"), None);
+    }
+
+    #[test]
+    fn test_format_from_file_name() {
+        assert_eq!(Format::from_file_name("Add.hack"), Some(Format::Hack));
+        assert_eq!(Format::from_file_name("dir/Add.hex"), Some(Format::Digital));
+        assert_eq!(Format::from_file_name("Add.coe"), Some(Format::Coe));
+        assert_eq!(Format::from_file_name("Add.mif"), Some(Format::Mif));
+        assert_eq!(Format::from_file_name("Add.json"), Some(Format::Json));
+        assert_eq!(Format::from_file_name("Add.img"), Some(Format::Logisim));
+        assert_eq!(Format::from_file_name("Add.txt"), None);
+        assert_eq!(Format::from_file_name("Add"), None);
+    }
+
+    #[test]
+    fn test_format_extension() {
+        for format in [Format::Hack, Format::Logisim, Format::Digital, Format::Coe,
+                       Format::Mif, Format::Json, Format::Listing] {
+            let file_name = format!("Add.{}", format.extension());
+            assert_eq!(Format::from_file_name(&file_name), Some(format));
+        }
+    }
+
+    #[test]
+    fn test_output_hack() {
+        assert_eq!(Output::hack(&[]), "");
+        assert_eq!(Output::hack(&[1, 0xfc10]),
+                   concat!("0000", "0000", "0000", "0001", "\n",
+                           "1111", "1100", "0001", "0000", "\n"));
+    }
+
+    #[test]
+    fn test_output_logisim() {
+        assert_eq!(Output::logisim(&[]), "v2.0 raw\n");
+        assert_eq!(Output::logisim(&[2, 0xec10, 0, 0]),
+                   "v2.0 raw\n2 ec10 0 0\n");
+        assert_eq!(Output::logisim(&[0, 0, 0, 0, 0, 1]),
+                   "v2.0 raw\n5*0 1\n");
+        assert_eq!(Output::logisim(&[0, 1, 2, 3, 4, 5, 6, 7, 8]),
+                   "v2.0 raw\n0 1 2 3 4 5 6 7\n8\n");
+    }
+
+    #[test]
+    fn test_output_digital() {
+        assert_eq!(Output::digital(&[]), "v2.0 raw\n");
+        assert_eq!(Output::digital(&[2, 0xec10, 0, 0]),
+                   "v2.0 raw\n2\nec10\n0\n0\n");
+    }
+
+    #[test]
+    fn test_output_coe() {
+        let options = RomOptions::default();
+        assert_eq!(&Output::coe(&[2, 0xec10], &options).unwrap(),
+                   concat!("memory_initialization_radix=16;\n",
+                           "memory_initialization_vector=\n",
+                           "0002,\n",
+                           "ec10;\n"));
+
+        let options = RomOptions { depth: Some(4), fill: Some(0xffff) };
+        assert_eq!(&Output::coe(&[2, 0xec10], &options).unwrap(),
+                   concat!("memory_initialization_radix=16;\n",
+                           "memory_initialization_vector=\n",
+                           "0002,\n",
+                           "ec10,\n",
+                           "ffff,\n",
+                           "ffff;\n"));
+
+        let options = RomOptions { depth: Some(1), fill: None };
+        assert_eq!(Output::coe(&[2, 0xec10], &options), None);
+
+        // 空のプログラムでもROMは1ワードになる
+        assert_eq!(&Output::coe(&[], &RomOptions::default()).unwrap(),
+                   concat!("memory_initialization_radix=16;\n",
+                           "memory_initialization_vector=\n",
+                           "0000;\n"));
+    }
+
+    #[test]
+    fn test_output_mif() {
+        let options = RomOptions::default();
+        assert_eq!(&Output::mif(&[2, 0xec10], &options).unwrap(),
+                   concat!("WIDTH=16;\n",
+                           "DEPTH=2;\n\n",
+                           "ADDRESS_RADIX=UNS;\n",
+                           "DATA_RADIX=HEX;\n\n",
+                           "CONTENT BEGIN\n",
+                           "\t0 : 0002;\n",
+                           "\t1 : ec10;\n",
+                           "END;\n"));
+
+        let options = RomOptions { depth: Some(3), fill: None };
+        assert!(Output::mif(&[2, 0xec10], &options).unwrap()
+                .contains("\t1 : ec10;\n\t2 : 0000;\nEND;\n"));
+
+        let options = RomOptions { depth: Some(32768), fill: None };
+        assert!(Output::mif(&[2, 0xec10], &options).unwrap()
+                .contains("\t1 : ec10;\n\t[2..32767] : 0000;\nEND;\n"));
+
+        let options = RomOptions { depth: Some(1), fill: None };
+        assert_eq!(Output::mif(&[2, 0xec10], &options), None);
+
+        let mif = Output::mif(&[], &RomOptions::default()).unwrap();
+        assert!(mif.contains("DEPTH=1;\n"));
+        assert!(mif.contains("CONTENT BEGIN\n\t0 : 0000;\nEND;\n"));
+    }
+    #[test]
+    fn test_output_json() {
+        let mut assembly = Assembly::new("Test.asm");
+        assert_eq!(Output::json(&assembly),
+                   concat!("{\n",
+                           "  \"file\": \"Test.asm\",\n",
+                           "  \"instructions\": [],\n",
+                           "  \"labels\": {},\n",
+                           "  \"variables\": {}\n",
+                           "}\n"));
+
+        assembly.instructions.push(Instruction {
+            line: 2,
+            text: "@i".to_string(),
+            address: 0,
+            word: 16,
+            kind: CommandType::A,
+            symbol: Some("i".to_string()),
+            pseudo: None,
+        });
+        assembly.labels.push(("LOOP".to_string(), 0));
+        assembly.variables.push(("i".to_string(), 16));
+        assert_eq!(Output::json(&assembly),
+                   concat!("{\n",
+                           "  \"file\": \"Test.asm\",\n",
+                           "  \"instructions\": [\n",
+                           "    {\"file\": \"Test.asm\", \"line\": 2, ",
+                           "\"text\": \"@i\", \"address\": 0, \"word\": 16, ",
+                           "\"binary\": \"0000000000010000\", \"kind\": \"A\", ",
+                           "\"symbol\": \"i\", \"pseudo\": null}\n",
+                           "  ],\n",
+                           "  \"labels\": {\"LOOP\": 0},\n",
+                           "  \"variables\": {\"i\": 16}\n",
+                           "}\n"));
+    }
+
+    #[test]
+    fn test_output_listing() {
+        let mut assembly = Assembly::new("Test.asm");
+        assert_eq!(Output::listing(&assembly), "");
+
+        assembly.instructions.push(Instruction {
+            line: 1,
+            text: "D=M".to_string(),
+            address: 0,
+            word: 0xfc10,
+            kind: CommandType::C,
+            symbol: None,
+            pseudo: None,
+        });
+        for (address, text, word) in [(1, "@LOOP", 0), (2, "0;JMP", 0xea87)] {
+            assembly.instructions.push(Instruction {
+                line: 12,
+                text: text.to_string(),
+                address,
+                word,
+                kind: CommandType::C,
+                symbol: None,
+                pseudo: Some("goto LOOP".to_string()),
+            });
+        }
+        assert_eq!(Output::listing(&assembly),
+                   concat!("    1     0  1111110000010000  D=M\n",
+                           "   12                          goto LOOP\n",
+                           "   12     1  0000000000000000    @LOOP\n",
+                           "   12     2  1110101010000111    0;JMP\n"));
+        assert_eq!(Format::from_file_name("Test.lst"), Some(Format::Listing));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("LOOP"), "\"LOOP\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\t\u{1}"), "\"\\t\\u0001\"");
+    }
+}
@@ -1,5 +1,8 @@
 // nand2tetris - 123 page
 
+use crate::pseudo::{self, Stack};
+use crate::syntax::{SyntaxTree, TokenKind};
+
 /// 主な機能は各アセンブリコマンドをその基本要素（フィールドとシンボル）に分解
 /// することである。具体的には入力コードへのアクセスをカプセル化し、アセンブリ
 /// 言語のコマンドを読み、それをパースし、コマンドの要素（フィールドと
@@ -7,16 +10,24 @@
 /// とコメントを削除する。
 pub struct Parser {
     asm_lines: Vec<String>, // 不要なデータを除外した行のvector
+    line_numbers: Vec<usize>, // asm_linesの各行のソース上の行番号
+    raw_lines: Vec<(String, usize)>, // 空白を削除する前の各行とその開始列
+    pseudo_lines: Vec<Option<String>>, // 各行が疑似命令を展開したものであればその疑似命令
+    warnings: Vec<(usize, usize, String)>, // 行番号、列番号、警告の内容
+    errors: Vec<(usize, usize, String)>, // 行番号、列番号、エラーの内容
     command: String, // 現在のコマンド
+    raw_command: (String, usize), // 空白を削除する前の現在のコマンドと開始列
+    pseudo: Option<String>, // 現在のコマンドのもとになった疑似命令
+    line_number: usize, // 現在のコマンドのソース上の行番号
     count: usize, // 現在の行数
 }
 
 /// * `CommandType::A`は`@Xxx`を意味し、`Xxx`はシンボルか10進数の数値である
 /// * `CommandType::C`は`dest=comp;jump`を意味する
 /// * `CommandType::L`は疑似コマンドであり、`(Xxx)`を意味する。`Xxx`は
-/// シンボルである
+///   シンボルである
 /// * `CommandType::None`は上記のどれにも該当しないことを意味する
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum CommandType {
     A,
     C,
@@ -24,46 +35,153 @@ pub enum CommandType {
     None,
 }
 
+/// コメントの書き方の設定。`//`から行末まではいつもコメントである
+/// * `block`は`/* ... */`を使えるか（複数行にまたがってもよい）
+/// * `semicolon`は`;`から行末までをコメントとするか。ただし`;`の後ろが
+///   jumpモーニックの場合はC命令の区切りとして扱う
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CommentSyntax {
+    pub block: bool,
+    pub semicolon: bool,
+}
+
+impl Default for CommentSyntax {
+    fn default() -> CommentSyntax {
+        CommentSyntax {
+            block: true,
+            semicolon: false,
+        }
+    }
+}
+
+/// `;`の後ろの文字列`rest`がjumpモーニックで始まるか？`ignore_case`が`true`
+/// のときは`jmp`のような小文字も受け付ける
+pub fn is_jump(rest: &str, ignore_case: bool) -> bool {
+    let monic: String = rest.trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let monic = if ignore_case { monic.to_ascii_uppercase() } else { monic };
+
+    ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"].contains(&monic.as_str())
+}
+
+/// シンボルとして使えない最初の文字の位置（文字単位）とその文字を返す。
+/// シンボルは英字、数字、`_`、`.`、`$`、`:`からなり、数字で始まらない
+pub fn invalid_symbol_char(symbol: &str) -> Option<(usize, char)> {
+    symbol.chars().enumerate().find(|(i, c)| {
+        let valid = c.is_ascii_alphabetic() || ['_', '.', '$', ':'].contains(c)
+            || (*i > 0 && c.is_ascii_digit());
+        !valid
+    })
+}
+
 impl Parser {
     /// `Parser`を初期化
-    /// `asm`はアセンブリ言語の文字列
+    /// `asm`はアセンブリ言語の文字列。空白文字（タブ、CRLFのCRなど）は
+    /// 命令の中にあるものも含めてすべて削除する。ニーモニックやシンボルの中の
+    /// 空白は削除する前の`raw_command()`で検査する
+    #[cfg(test)]
     pub fn new(asm: String) -> Parser {
-        let lines = asm.lines();
-        let mut asm_lines = Vec::new();
+        Parser::with_comments(asm, CommentSyntax::default())
+    }
 
-        // 不要な行や空白を除外する
-        for line in lines {
-            let mut line = line;
+    /// `comments`の書き方のコメントを削除する`Parser`を初期化
+    #[cfg(test)]
+    pub fn with_comments(asm: String, comments: CommentSyntax) -> Parser {
+        Parser::with_options(asm, comments, Stack::default(), false)
+    }
 
-            // コメントの削除
-            let comment: Vec<_> = line.match_indices("//").collect();
-            if comment.len() != 0 {
-                line = line.get(..comment[0].0).unwrap();
+    /// `comments`の書き方のコメントを削除する`Parser`を初期化。`goto LOOP`の
+    /// ような疑似命令はここで展開し、`push`と`pop`は`stack`の向きのスタックを使う。
+    /// `ignore_case`が`true`のときは`;`の後ろのjumpと疑似命令の大文字と小文字を
+    /// 区別しない。各コマンドはソースの構文木（`SyntaxTree`）の行から取り出す
+    pub fn with_options(asm: String, comments: CommentSyntax, stack: Stack,
+                        ignore_case: bool) -> Parser {
+        let syntax = SyntaxTree::parse(&asm, comments, ignore_case);
+        let mut asm_lines = Vec::new();
+        let mut line_numbers = Vec::new();
+        let mut raw_lines = Vec::new();
+        let mut pseudo_lines = Vec::new();
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+
+        for line in &syntax.lines {
+            // ASCII以外の空白（ノーブレークスペースなど）は警告する
+            for token in line.tokens.iter().filter(|t| t.kind == TokenKind::Whitespace) {
+                for (i, c) in token.text.chars().enumerate() {
+                    if !c.is_ascii() {
+                        warnings.push((line.number, token.column + i,
+                            format!("non-ASCII whitespace U+{:04X}", c as u32)));
+                    }
+                }
             }
-            
-            // 空白の削除
-            line = line.trim_matches(' ');
 
-            if line.len() == 0 {
-                continue;
+            let (raw, column) = match line.raw_command() {
+                Some(raw) => raw,
+                None => continue,
+            };
+
+            // 疑似命令の展開。展開した`@Xxx`の列はオペランドの位置に合わせる
+            match pseudo::expand(&raw, line.number, stack, ignore_case) {
+                Some(Ok(expansion)) => {
+                    for (command, offset) in expansion {
+                        let column = if offset == 0 { column } else { column - 1 + offset };
+                        asm_lines.push(command.clone());
+                        line_numbers.push(line.number);
+                        raw_lines.push((command, column));
+                        pseudo_lines.push(Some(raw.clone()));
+                    }
+                    continue;
+                },
+                Some(Err(message)) => {
+                    errors.push((line.number, column, message));
+                    continue;
+                },
+                None => (),
             }
 
-            asm_lines.push(line.to_string());
+            asm_lines.push(line.command());
+            line_numbers.push(line.number);
+            raw_lines.push((raw, column));
+            pseudo_lines.push(None);
+        }
+
+        let unterminated = syntax.tokens().find(|t| {
+            t.kind == TokenKind::BlockComment && (t.text.len() < 4 || !t.text.ends_with("*/"))
+        });
+        if let Some(token) = unterminated {
+            errors.push((token.line, token.column, "unterminated block comment".to_string()));
         }
 
         Parser {
             asm_lines,
+            line_numbers,
+            raw_lines,
+            pseudo_lines,
+            warnings,
+            errors,
             command: String::new(),
+            raw_command: (String::new(), 0),
+            pseudo: None,
+            line_number: 0,
             count: 0,
         }
     }
 
+    /// 空白の削除で見つかった警告を（行番号、列番号、内容）の形で返す
+    pub fn warnings(&self) -> &[(usize, usize, String)] {
+        &self.warnings
+    }
+
+    /// コメントの削除で見つかったエラーを（行番号、列番号、内容）の形で返す
+    pub fn errors(&self) -> &[(usize, usize, String)] {
+        &self.errors
+    }
+
     /// 入力にまだコマンドが存在するか？
     pub fn has_more_commands(&self) -> bool {
-        if self.count < self.asm_lines.len() {
-            return true
-        }
-        return false
+        self.count < self.asm_lines.len()
     }
 
     /// 入力から次のコマンドを読み、それを現在のコマンドにする。このルーチンは
@@ -71,30 +189,53 @@ impl Parser {
     /// は空である。
     pub fn advance(&mut self) {
         self.command = self.asm_lines[self.count].to_string();
+        self.line_number = self.line_numbers[self.count];
+        self.raw_command = self.raw_lines[self.count].clone();
+        self.pseudo = self.pseudo_lines[self.count].clone();
         self.count += 1;
     }
 
+    /// 現コマンドの文字列を返す
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// 空白を削除する前の現コマンドの文字列と、そのソース上の開始列（1から
+    /// 始まる）を返す
+    pub fn raw_command(&self) -> (&str, usize) {
+        (&self.raw_command.0, self.raw_command.1)
+    }
+
+    /// 現コマンドが疑似命令を展開したものであれば、その疑似命令を返す
+    pub fn pseudo(&self) -> Option<&str> {
+        self.pseudo.as_deref()
+    }
+
+    /// 現コマンドのソース上の行番号を返す（1から始まる）。最初は0である
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
     /// 現コマンドの種類を返す
     /// * `CommandType::A`は`@Xxx`を意味し、`Xxx`はシンボルか10進数の数値である
     /// * `CommandType::C`は`dest=comp;jump`を意味する
     /// * `CommandType::L`は疑似コマンドであり、`(Xxx)`を意味する。`Xxx`は
-    /// シンボルである
+    ///   シンボルである
     /// * `CommandType::None`は上記のどれにも該当しないことを意味する
     pub fn command_type(&self) -> CommandType {
-        if self.command.chars().next() == Some('@') {
+        if self.command.starts_with('@') {
             return CommandType::A
         }
 
-        if self.command.find('=') != None || self.command.find(';') != None {
+        if self.command.contains('=') || self.command.contains(';') {
             return CommandType::C
         }
 
-        if self.command.chars().next() == Some('(') 
-           && self.command.chars().last() == Some(')') {
-               return CommandType::L
+        if self.command.starts_with('(') && self.command.ends_with(')') {
+            return CommandType::L
         }
 
-        return CommandType::None
+        CommandType::None
     }
 
     /// 現コマンドの`@Xxx`または`(Xxx)`の`Xxx`を返す。Xxxはシンボルまたは10進数
@@ -103,9 +244,8 @@ impl Parser {
     pub fn symbol(&self) -> String {
         let mut chars = self.command.chars();
         match chars.next() {
-            Some('@') => return self.command[1..].to_string(),
-            Some('(') => return self.command[1..self.command.len()-1]
-                                .to_string(),
+            Some('@') => self.command[1..].to_string(),
+            Some('(') => self.command[1..self.command.len()-1].to_string(),
             _ => "".to_string()
         }
     }
@@ -118,7 +258,7 @@ impl Parser {
             return self.command[..n].to_string()
         }
 
-        return "".to_string()
+        "".to_string()
     }
 
     /// 現C命令のcompモーニックを返す（候補として28つの可能性がある）。
@@ -136,7 +276,7 @@ impl Parser {
             return self.command[..n].to_string();
         }
         
-        return "".to_string()
+        "".to_string()
     }
 
     /// 現C命令のjumpモーニックを返す（候補として8つの可能性がある）。
@@ -147,7 +287,7 @@ impl Parser {
             return self.command[n+1..].to_string()
         }
 
-        return "".to_string()
+        "".to_string()
     }
 }
 
@@ -155,6 +295,8 @@ impl Parser {
 mod test {
     use super::Parser;
     use super::CommandType;
+    use super::CommentSyntax;
+    use super::invalid_symbol_char;
 
     #[test]
     fn test_parser_new() {
@@ -175,23 +317,124 @@ mod test {
         assert_eq!(parser.asm_lines, vec!["@test", "@1"]);
     }
 
+    #[test]
+    fn test_parser_new_whitespace() {
+        let asm = "\t@R0\n\tD = M ; JGT\t// test\n  ( LOOP )\n".to_string();
+        let parser = Parser::new(asm);
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M;JGT", "(LOOP)"]);
+        assert_eq!(parser.warnings(), []);
+
+        // Windowsで作られたファイル（BOMとCRLF）
+        let asm = "\u{feff}// test\r\n@R0\r\n\r\nD=M\r\n".to_string();
+        let parser = Parser::new(asm);
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M"]);
+        assert_eq!(parser.line_numbers, vec![2, 4]);
+
+        let asm = "@R0\nD=M\u{a0}// test\n".to_string();
+        let parser = Parser::new(asm);
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M"]);
+        assert_eq!(parser.warnings(),
+                   [(2, 4, "non-ASCII whitespace U+00A0".to_string())]);
+    }
+
+    #[test]
+    fn test_parser_block_comments() {
+        let asm = r#"
+        /* test
+           @ignored
+        */ @R0 /* test */
+        D=M /* test
+        */
+        "#.to_string();
+        let parser = Parser::new(asm.clone());
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M"]);
+        assert_eq!(parser.line_numbers, vec![4, 5]);
+        assert_eq!(parser.errors(), []);
+
+        let comments = CommentSyntax { block: false, semicolon: false };
+        let parser = Parser::with_comments(asm, comments);
+        assert_eq!(parser.asm_lines[0], "/*test");
+
+        let asm = "@R0\n  D=M /* test\n@R1".to_string();
+        let parser = Parser::new(asm);
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M"]);
+        assert_eq!(parser.errors(), [(2, 7, "unterminated block comment".to_string())]);
+    }
+
+    #[test]
+    fn test_parser_semicolon_comments() {
+        let asm = r#"
+        ; test
+        @R0 ; test
+        D=M;JGT ; test
+        0 ; JMP
+        D;test
+        "#.to_string();
+        let comments = CommentSyntax { block: true, semicolon: true };
+        let parser = Parser::with_comments(asm.clone(), comments);
+        assert_eq!(parser.asm_lines, vec!["@R0", "D=M;JGT", "0;JMP", "D"]);
+        assert_eq!(parser.line_numbers, vec![3, 4, 5, 6]);
+
+        let parser = Parser::new(asm);
+        assert_eq!(parser.asm_lines[0], ";test");
+    }
+
     #[test]
     fn test_parser_has_more_commands() {
         let asm = r#""#.to_string();
         let parser = Parser::new(asm);
         assert_eq!(parser.asm_lines.len(), 0);
-        assert_eq!(parser.has_more_commands(), false);
+        assert!(!parser.has_more_commands());
 
         let asm = r#"
         @test
         @test
         "#.to_string();
         let mut parser = Parser::new(asm);
-        assert_eq!(parser.has_more_commands(), true);
+        assert!(parser.has_more_commands());
+        parser.advance();
+        assert!(parser.has_more_commands());
+        parser.advance();
+        assert!(!parser.has_more_commands());
+    }
+
+    #[test]
+    fn test_parser_line_number() {
+        let asm = r#"
+        // test asm
+        @test
+
+        D=M // test
+        "#.to_string();
+        let mut parser = Parser::new(asm);
+        assert_eq!(parser.line_number(), 0);
         parser.advance();
-        assert_eq!(parser.has_more_commands(), true);
+        assert_eq!(parser.command(), "@test");
+        assert_eq!(parser.line_number(), 3);
         parser.advance();
-        assert_eq!(parser.has_more_commands(), false);
+        assert_eq!(parser.command(), "D=M");
+        assert_eq!(parser.line_number(), 5);
+    }
+
+    #[test]
+    fn test_parser_raw_command() {
+        let asm = "\t( my label ) // test\n  D = M\n".to_string();
+        let mut parser = Parser::new(asm);
+        parser.advance();
+        assert_eq!(parser.command(), "(mylabel)");
+        assert_eq!(parser.raw_command(), ("( my label )", 2));
+        parser.advance();
+        assert_eq!(parser.raw_command(), ("D = M", 3));
+    }
+
+    #[test]
+    fn test_invalid_symbol_char() {
+        assert_eq!(invalid_symbol_char("LOOP"), None);
+        assert_eq!(invalid_symbol_char("Main.fib$ret.1"), None);
+        assert_eq!(invalid_symbol_char("_a:b"), None);
+        assert_eq!(invalid_symbol_char("1abc"), Some((0, '1')));
+        assert_eq!(invalid_symbol_char("my label"), Some((2, ' ')));
+        assert_eq!(invalid_symbol_char("a(b)"), Some((1, '(')));
     }
 
     #[test]
@@ -0,0 +1,292 @@
+// 疑似命令の展開
+
+use crate::code::Code;
+
+/// 展開した命令と、そのもとになったオペランドの疑似命令の中での位置（文字
+/// 単位、0は疑似命令の先頭）のリスト
+pub type Expansion = Vec<(String, usize)>;
+
+/// `push`と`pop`のスタックの伸びる向き
+/// * `Stack::Up`はnand2tetrisのVMと同じく、`SP`が次に積む番地を指し、
+///   番地の大きい方へ伸びる
+/// * `Stack::Down`は`SP`が最後に積んだ番地を指し、番地の小さい方へ伸びる
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Stack {
+    #[default]
+    Up,
+    Down,
+}
+
+/// 条件付きの`goto`以外の疑似命令の名前
+const NAMES: [&str; 7] = ["goto", "inc", "clr", "mov", "halt", "push", "pop"];
+
+/// jumpの条件付きの`goto`
+const CONDITIONS: [(&str, &str); 6] = [
+    ("if-D-gt", "JGT"),
+    ("if-D-eq", "JEQ"),
+    ("if-D-ge", "JGE"),
+    ("if-D-lt", "JLT"),
+    ("if-D-ne", "JNE"),
+    ("if-D-le", "JLE"),
+];
+
+/// `line`のカンマで区切られたオペランドを、`start`からの位置とともに返す
+fn operands(line: &str, start: usize) -> Vec<(&str, usize)> {
+    let rest = &line[start..];
+    if rest.trim().is_empty() {
+        return Vec::new()
+    }
+
+    let mut operands = Vec::new();
+    let mut offset = start;
+    for operand in rest.split(',') {
+        let indent = operand.len() - operand.trim_start().len();
+        operands.push((operand.trim(), line[..offset + indent].chars().count()));
+        offset += operand.len() + 1;
+    }
+
+    operands
+}
+
+/// オペランドがレジスタ（destモーニック）であれば本の順番に並べて返す
+fn register(operand: &str) -> Option<String> {
+    if operand.is_empty() {
+        return None
+    }
+
+    Code::canonical_dest(operand)
+}
+
+/// 疑似命令の行（コメントを除いて前後の空白を削除したもの）を展開する。
+/// 疑似命令でない場合は`None`を返す。`line_number`は`halt`のラベルに使い、
+/// `stack`は`push`と`pop`のスタックの向き。`ignore_case`が`true`のときは
+/// 疑似命令の名前とレジスタのオペランドの大文字と小文字を区別しない
+/// * `goto LABEL`は`LABEL`へジャンプする
+/// * `if-D-gt LABEL`などはDの値が条件を満たすとき`LABEL`へジャンプする
+/// * `mov DEST, VALUE`は`DEST`（レジスタまたはRAMのアドレス）に`VALUE`
+///   （定数、シンボル、`D`など）を入れる。`mov M, 17`はDの値を壊す
+/// * `inc DEST`と`clr DEST`は`DEST`に1を加える、0にする
+/// * `halt`は自分自身へジャンプし続ける
+/// * `push R`と`pop R`（RはA、D、M）は`SP`のスタックに積む、取り出す。
+///   `push A`、`push M`、`pop M`はDの値を壊す
+pub fn expand(line: &str, line_number: usize, stack: Stack,
+              ignore_case: bool) -> Option<Result<Expansion, String>> {
+    let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+    let name = NAMES.iter().copied()
+        .chain(CONDITIONS.iter().map(|(n, _)| *n))
+        .find(|n| *n == &line[..name_end]
+                  || (ignore_case && n.eq_ignore_ascii_case(&line[..name_end])))?;
+    let arity = match name {
+        "mov" => 2,
+        "halt" => 0,
+        _ => 1,
+    };
+
+    // レジスタのオペランドは本の書き方（大文字）にそろえる
+    let operands = operands(line, name_end);
+    let upper: Vec<String> = operands.iter().map(|(o, _)| o.to_ascii_uppercase()).collect();
+    let operands: Vec<(&str, usize)> = operands.iter().zip(&upper)
+        .map(|(&(operand, column), upper)| {
+            let register = ignore_case && register(upper).is_some();
+            (if register { upper.as_str() } else { operand }, column)
+        })
+        .collect();
+    if operands.len() != arity || operands.iter().any(|(o, _)| o.is_empty()) {
+        return Some(Err(format!("`{}` expects {} operand(s)", name, arity)))
+    }
+
+    let at = |(operand, column): (&str, usize)| (format!("@{}", operand), column);
+    let c = |command: String| (command, 0);
+    let expansion = match name {
+        "goto" => vec![at(operands[0]), c("0;JMP".to_string())],
+        "inc" => match register(operands[0].0) {
+            Some(r) if r.len() == 1 => vec![c(format!("{}={}+1", r, r))],
+            Some(r) => return Some(Err(format!("`inc` can't add 1 to `{}`", r))),
+            None => vec![at(operands[0]), c("M=M+1".to_string())],
+        },
+        "clr" => match register(operands[0].0) {
+            Some(r) => vec![c(format!("{}=0", r))],
+            None => vec![at(operands[0]), c("M=0".to_string())],
+        },
+        "mov" => return Some(mov(operands[0], operands[1])),
+        "push" | "pop" => return Some(stack_operation(name, operands[0].0, stack)),
+        "halt" => {
+            // 空白を含む名前はソースのシンボルとしては書けないので、利用者の
+            // ラベルとぶつからない
+            let label = format!("halt {}", line_number);
+            vec![c(format!("({})", label)), c(format!("@{}", label)), c("0;JMP".to_string())]
+        },
+        _ => {
+            let (_, jump) = CONDITIONS.iter().find(|(n, _)| *n == name).unwrap();
+            vec![at(operands[0]), c(format!("D;{}", jump))]
+        },
+    };
+
+    Some(Ok(expansion))
+}
+
+/// `mov DEST, VALUE`を展開する
+fn mov(dest: (&str, usize), value: (&str, usize)) -> Result<Expansion, String> {
+    let c = |command: &str| (command.to_string(), 0);
+    let at = |(operand, column): (&str, usize)| (format!("@{}", operand), column);
+    let constant = ["0", "1", "-1"].contains(&value.0);
+    let source = register(value.0).filter(|r| r.len() == 1);
+
+    let register = match register(dest.0) {
+        Some(register) => register,
+        // RAMのアドレスへの代入
+        None => return match source.as_deref() {
+            _ if constant => Ok(vec![at(dest), c(&format!("M={}", value.0))]),
+            Some("D") => Ok(vec![at(dest), c("M=D")]),
+            Some(r) => Err(format!("`mov` can't store `{}` to an address", r)),
+            None => Ok(vec![at(value), c("D=A"), at(dest), c("M=D")]),
+        }
+    };
+
+    if constant || source.is_some() {
+        return Ok(vec![c(&format!("{}={}", register, value.0))])
+    }
+
+    match register.as_str() {
+        "A" => Ok(vec![at(value)]),
+        "D" | "AD" => Ok(vec![at(value), c("D=A")]),
+        // Aを保ったままMに入れる。D=アドレス+VALUE、A=アドレス、M=VALUEとなる
+        "M" | "MD" => {
+            let mut expansion = vec![c("D=A"), at(value), c("D=D+A"), c("A=D-A"), c("M=D-A")];
+            if register == "MD" {
+                expansion.push(c("D=M"));
+            }
+            Ok(expansion)
+        },
+        _ => Err(format!("`mov` can't load `{}` into `{}`", value.0, register)),
+    }
+}
+
+/// `push R`または`pop R`を展開する
+fn stack_operation(name: &str, operand: &str, stack: Stack) -> Result<Expansion, String> {
+    let register = match register(operand) {
+        Some(r) if r.len() == 1 => r,
+        _ => return Err(format!("`{}` expects A, D or M but found `{}`", name, operand)),
+    };
+
+    // SPを動かしてAをスタックの先頭の番地にする
+    let (push, pop) = match stack {
+        Stack::Up => (["@SP", "M=M+1", "A=M-1"].as_slice(), ["@SP", "AM=M-1"].as_slice()),
+        Stack::Down => (["@SP", "AM=M-1"].as_slice(), ["@SP", "M=M+1", "A=M-1"].as_slice()),
+    };
+
+    let mut commands = Vec::new();
+    if name == "push" {
+        if register != "D" {
+            commands.push(format!("D={}", register));
+        }
+        commands.extend(push.iter().map(|c| c.to_string()));
+        commands.push("M=D".to_string());
+    } else if register == "M" {
+        // Aを保ったままMに入れる。D=アドレス+値、A=アドレス、M=値となる
+        commands.push("D=A".to_string());
+        commands.extend(pop.iter().map(|c| c.to_string()));
+        commands.extend(["D=D+M", "A=D-M", "M=D-A"].map(|c| c.to_string()));
+    } else {
+        commands.extend(pop.iter().map(|c| c.to_string()));
+        commands.push(format!("{}=M", register));
+    }
+
+    Ok(commands.into_iter().map(|c| (c, 0)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{expand, Stack};
+
+    fn texts(line: &str) -> Vec<String> {
+        expand(line, 1, Stack::Up, false).unwrap().unwrap()
+            .into_iter().map(|(text, _)| text).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("@LOOP", 1, Stack::Up, false), None);
+        assert_eq!(expand("D=M", 1, Stack::Up, false), None);
+        assert_eq!(expand("gotoLOOP", 1, Stack::Up, false), None);
+
+        assert_eq!(expand("goto LOOP", 1, Stack::Up, false),
+                   Some(Ok(vec![("@LOOP".to_string(), 5), ("0;JMP".to_string(), 0)])));
+        assert_eq!(texts("if-D-gt  POSITIVE"), vec!["@POSITIVE", "D;JGT"]);
+        assert_eq!(texts("if-D-ne END"), vec!["@END", "D;JNE"]);
+        assert_eq!(texts("inc i"), vec!["@i", "M=M+1"]);
+        assert_eq!(texts("inc D"), vec!["D=D+1"]);
+        assert_eq!(texts("clr R1"), vec!["@R1", "M=0"]);
+        assert_eq!(texts("clr DM"), vec!["MD=0"]);
+        assert_eq!(expand("halt", 7, Stack::Up, false),
+                   Some(Ok(vec![("(halt 7)".to_string(), 0), ("@halt 7".to_string(), 0),
+                                ("0;JMP".to_string(), 0)])));
+    }
+
+    #[test]
+    fn test_expand_mov() {
+        assert_eq!(texts("mov D, 17"), vec!["@17", "D=A"]);
+        assert_eq!(texts("mov A,SCREEN"), vec!["@SCREEN"]);
+        assert_eq!(texts("mov M, -1"), vec!["M=-1"]);
+        assert_eq!(texts("mov M, D"), vec!["M=D"]);
+        assert_eq!(texts("mov M, 17"), vec!["D=A", "@17", "D=D+A", "A=D-A", "M=D-A"]);
+        assert_eq!(texts("mov i, 17"), vec!["@17", "D=A", "@i", "M=D"]);
+        assert_eq!(texts("mov i, 0"), vec!["@i", "M=0"]);
+        assert_eq!(texts("mov i, D"), vec!["@i", "M=D"]);
+        let expansion = expand("mov i, 17", 1, Stack::Up, false).unwrap().unwrap();
+        assert_eq!(expansion[2], ("@i".to_string(), 4));
+        assert_eq!(expansion[0], ("@17".to_string(), 7));
+    }
+
+    #[test]
+    fn test_expand_stack() {
+        assert_eq!(texts("push D"), vec!["@SP", "M=M+1", "A=M-1", "M=D"]);
+        assert_eq!(texts("push M"), vec!["D=M", "@SP", "M=M+1", "A=M-1", "M=D"]);
+        assert_eq!(texts("pop D"), vec!["@SP", "AM=M-1", "D=M"]);
+        assert_eq!(texts("pop A"), vec!["@SP", "AM=M-1", "A=M"]);
+        assert_eq!(texts("pop M"), vec!["D=A", "@SP", "AM=M-1", "D=D+M", "A=D-M", "M=D-A"]);
+
+        let texts = |line| -> Vec<String> {
+            expand(line, 1, Stack::Down, false).unwrap().unwrap()
+                .into_iter().map(|(t, _)| t).collect()
+        };
+        assert_eq!(texts("push A"), vec!["D=A", "@SP", "AM=M-1", "M=D"]);
+        assert_eq!(texts("pop D"), vec!["@SP", "M=M+1", "A=M-1", "D=M"]);
+
+        assert_eq!(expand("push 17", 1, Stack::Up, false),
+                   Some(Err("`push` expects A, D or M but found `17`".to_string())));
+        assert_eq!(expand("pop AD", 1, Stack::Up, false),
+                   Some(Err("`pop` expects A, D or M but found `AD`".to_string())));
+    }
+
+    #[test]
+    fn test_expand_ignore_case() {
+        let texts = |line: &str| -> Vec<String> {
+            expand(line, 1, Stack::Up, true).unwrap().unwrap()
+                .into_iter().map(|(t, _)| t).collect()
+        };
+        assert_eq!(texts("GOTO LOOP"), vec!["@LOOP", "0;JMP"]);
+        assert_eq!(texts("If-d-GT end"), vec!["@end", "D;JGT"]);
+        assert_eq!(texts("push d"), vec!["@SP", "M=M+1", "A=M-1", "M=D"]);
+        assert_eq!(texts("mov md, a"), vec!["MD=A"]);
+        assert_eq!(expand("GOTO LOOP", 1, Stack::Up, false), None);
+        assert_eq!(expand("push d", 1, Stack::Up, false),
+                   Some(Err("`push` expects A, D or M but found `d`".to_string())));
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        assert_eq!(expand("goto", 1, Stack::Up, false),
+                   Some(Err("`goto` expects 1 operand(s)".to_string())));
+        assert_eq!(expand("halt now", 1, Stack::Up, false),
+                   Some(Err("`halt` expects 0 operand(s)".to_string())));
+        assert_eq!(expand("mov D,", 1, Stack::Up, false),
+                   Some(Err("`mov` expects 2 operand(s)".to_string())));
+        assert_eq!(expand("inc AD", 1, Stack::Up, false),
+                   Some(Err("`inc` can't add 1 to `AD`".to_string())));
+        assert_eq!(expand("mov i, M", 1, Stack::Up, false),
+                   Some(Err("`mov` can't store `M` to an address".to_string())));
+        assert_eq!(expand("mov AM, 17", 1, Stack::Up, false),
+                   Some(Err("`mov` can't load `17` into `AM`".to_string())));
+    }
+}
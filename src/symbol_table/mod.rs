@@ -2,35 +2,190 @@
 
 use std::collections::HashMap;
 
+/// 定義済みシンボルとそのアドレス
+pub const PREDEFINED: [(&str, usize); 23] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", 0x4000),
+    ("KBD", 0x6000),
+];
+
+/// シンボルの種類
+/// * `SymbolKind::Predefined`は`SP`や`R0`などの定義済みシンボル
+/// * `SymbolKind::Label`は`(Xxx)`で定義したラベル
+/// * `SymbolKind::Variable`はアセンブラがアドレスを割り当てた変数
+/// * `SymbolKind::Constant`はソースの外（`-D`や`--symbols`）から定義した定数
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Predefined,
+    Label,
+    Variable,
+    Constant,
+    /// 疑似命令の展開で作ったラベル（`halt`など）。ラベルの一覧や相互参照表には出さない
+    Internal,
+}
+
+impl SymbolKind {
+    /// 種類の名前
+    pub fn name(self) -> &'static str {
+        match self {
+            SymbolKind::Predefined => "predefined",
+            SymbolKind::Label => "label",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Internal => "internal",
+        }
+    }
+}
+
+/// 表に登録されたシンボル
+/// * `name`は最初に登録したときの綴り
+/// * `address`はシンボルの値
+/// * `definition`は定義した行番号。ラベルは`(Xxx)`の行、変数は最初に参照した
+///   行で、ソースの外から定義したシンボルは`None`
+/// * `references`は`@Xxx`で参照した行番号（出現順）
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub kind: SymbolKind,
+    pub definition: Option<usize>,
+    pub references: Vec<usize>,
+}
+
 /// symbolの名前とアドレスを管理するためのモジュール
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default)]
 pub struct SymbolTable {
-    table: HashMap<String, usize>
+    symbols: Vec<Symbol>, // 登録した順のシンボル
+    index: HashMap<String, usize>, // キーからsymbolsの位置への対応
+    ignore_case: bool, // シンボルの大文字と小文字を区別しないか
 }
 
 impl SymbolTable {
     pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// シンボルの大文字と小文字を区別しない`SymbolTable`を作成する
+    pub fn case_insensitive() -> SymbolTable {
         SymbolTable {
-            table: HashMap::new()
+            ignore_case: true,
+            ..SymbolTable::default()
+        }
+    }
+
+    /// 表の中で`symbol`を探すときのキーを返す
+    pub fn key(&self, symbol: &str) -> String {
+        if self.ignore_case {
+            symbol.to_ascii_uppercase()
+        } else {
+            symbol.to_string()
         }
     }
 
+    /// `kind`のシンボルを加える。同じシンボルがすでにある場合は置き換える
+    pub fn add_symbol(&mut self, symbol: &str, address: usize, kind: SymbolKind,
+                      definition: Option<usize>) {
+        let entry = Symbol {
+            name: symbol.to_string(),
+            address,
+            kind,
+            definition,
+            references: Vec::new(),
+        };
+        match self.index.get(&self.key(symbol)) {
+            Some(&i) => self.symbols[i] = entry,
+            None => {
+                self.index.insert(self.key(symbol), self.symbols.len());
+                self.symbols.push(entry);
+            }
+        }
+    }
+
+    /// 定数のシンボルを加える
     pub fn add_entry(&mut self, symbol: &str, address: usize) {
-        self.table.insert(symbol.to_string(), address);
+        self.add_symbol(symbol, address, SymbolKind::Constant, None);
+    }
+
+    /// `line`行目で定義されたラベルを加える。すでに同じシンボルがある場合は
+    /// 置き換えず、その定義の行番号（ソースの外で定義されたシンボルは`None`）を
+    /// 返す
+    pub fn add_label(&mut self, symbol: &str, address: usize, line: usize)
+        -> Result<(), Option<usize>> {
+        if let Some(first) = self.get(symbol) {
+            return Err(first.definition)
+        }
+        self.add_symbol(symbol, address, SymbolKind::Label, Some(line));
+        Ok(())
+    }
+
+    /// `symbol`を`line`行目で参照したことを記録する。表にないシンボルは無視する
+    pub fn add_reference(&mut self, symbol: &str, line: usize) {
+        if let Some(&i) = self.index.get(&self.key(symbol)) {
+            self.symbols[i].references.push(line);
+        }
     }
 
     pub fn contains(&self, symbol: &str) -> bool {
-        self.table.contains_key(symbol)
+        self.index.contains_key(&self.key(symbol))
     }
 
     pub fn get_address(&self, symbol: &str) -> Option<&usize> {
-        self.table.get(symbol)
+        self.get(symbol).map(|s| &s.address)
+    }
+
+    /// `symbol`の登録内容を返す
+    pub fn get(&self, symbol: &str) -> Option<&Symbol> {
+        self.index.get(&self.key(symbol)).map(|&i| &self.symbols[i])
+    }
+
+    /// 登録した順にシンボルを返す
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    /// `kind`のシンボルを登録した順に返す
+    pub fn of_kind(&self, kind: SymbolKind) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(move |s| s.kind == kind)
+    }
+
+    /// ROMの`address`を指すラベルを返す
+    pub fn labels_at(&self, address: usize) -> impl Iterator<Item = &Symbol> {
+        self.of_kind(SymbolKind::Label).filter(move |s| s.address == address)
+    }
+}
+
+/// 登録した順や種類、行番号によらず、同じシンボルが同じアドレスを持てば等しい
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &SymbolTable) -> bool {
+        self.ignore_case == other.ignore_case
+            && self.index.len() == other.index.len()
+            && self.iter().all(|s| other.get_address(&s.name) == Some(&s.address))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::SymbolTable;
+    use super::{SymbolKind, SymbolTable};
 
     #[test]
     fn test_symbol_table_new() {
@@ -48,8 +203,8 @@ mod test {
     fn test_symbol_table_contains() {
         let mut symbol_table = SymbolTable::new();
         symbol_table.add_entry("test", 10);
-        assert_eq!(symbol_table.contains("test"), true);
-        assert_eq!(symbol_table.contains("m"), false);
+        assert!(symbol_table.contains("test"));
+        assert!(!symbol_table.contains("m"));
     }
 
     #[test]
@@ -58,4 +213,74 @@ mod test {
         symbol_table.add_entry("test", 10);
         assert_eq!(symbol_table.get_address("test"), Some(&10));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_symbol_table_add_label() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.add_entry("SP", 0);
+        assert_eq!(symbol_table.add_label("LOOP", 4, 3), Ok(()));
+        assert_eq!(symbol_table.add_label("LOOP", 9, 12), Err(Some(3)));
+        assert_eq!(symbol_table.add_label("SP", 9, 13), Err(None));
+        assert_eq!(symbol_table.get_address("SP"), Some(&0));
+        assert_eq!(symbol_table.get_address("LOOP"), Some(&4));
+        assert_eq!(symbol_table.get("LOOP").unwrap().definition, Some(3));
+        assert_eq!(symbol_table.get("SP").unwrap().definition, None);
+    }
+
+    #[test]
+    fn test_symbol_table_eq() {
+        let mut a = SymbolTable::new();
+        a.add_entry("TEST", 0);
+        a.add_entry("SYMBOL", 1);
+        let mut b = SymbolTable::new();
+        b.add_label("SYMBOL", 1, 4).unwrap();
+        b.add_label("TEST", 0, 2).unwrap();
+        assert_eq!(a, b);
+
+        b.add_entry("SYMBOL", 2);
+        assert_ne!(a, b);
+        a.add_entry("SYMBOL", 2);
+        a.add_entry("OTHER", 3);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_symbol_table_case_insensitive() {
+        let mut symbol_table = SymbolTable::case_insensitive();
+        symbol_table.add_entry("Loop", 10);
+        assert!(symbol_table.contains("LOOP"));
+        assert_eq!(symbol_table.get_address("loop"), Some(&10));
+        assert_eq!(symbol_table.key("sp"), "SP");
+
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.add_entry("Loop", 10);
+        assert!(!symbol_table.contains("LOOP"));
+    }
+
+    #[test]
+    fn test_symbol_table_kinds_and_references() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.add_symbol("SP", 0, SymbolKind::Predefined, None);
+        symbol_table.add_label("LOOP", 2, 3).unwrap();
+        symbol_table.add_label("END", 2, 5).unwrap();
+        symbol_table.add_symbol("i", 16, SymbolKind::Variable, Some(1));
+        symbol_table.add_entry("TIMER", 0x6001);
+        symbol_table.add_reference("i", 1);
+        symbol_table.add_reference("LOOP", 4);
+        symbol_table.add_reference("i", 6);
+        symbol_table.add_reference("x", 7);
+
+        let i = symbol_table.get("i").unwrap();
+        assert_eq!((i.kind, i.definition, &i.references),
+                   (SymbolKind::Variable, Some(1), &vec![1, 6]));
+        assert_eq!(symbol_table.get("TIMER").unwrap().kind, SymbolKind::Constant);
+        assert_eq!(symbol_table.get("x"), None);
+
+        let names = |symbols: Vec<&super::Symbol>| {
+            symbols.iter().map(|s| s.name.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(names(symbol_table.iter().collect()), vec!["SP", "LOOP", "END", "i", "TIMER"]);
+        assert_eq!(names(symbol_table.of_kind(SymbolKind::Label).collect()), vec!["LOOP", "END"]);
+        assert_eq!(names(symbol_table.labels_at(2).collect()), vec!["LOOP", "END"]);
+    }
+}
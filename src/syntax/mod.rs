@@ -0,0 +1,297 @@
+// ソースの字句と構文木。空白、改行、コメントも字句として残すので、字句の
+// 文字列を順につなげると元のソースに戻る
+
+use std::ops::Range;
+
+use crate::parser::{is_jump, CommentSyntax};
+
+/// 字句の種類
+/// * `TokenKind::Bom`はファイル先頭のBOM（U+FEFF）
+/// * `TokenKind::Newline`は改行（`\n`または`\r\n`）
+/// * `TokenKind::Whitespace`は改行以外の空白の並び
+/// * `TokenKind::LineComment`は`//`（または設定によって`;`）から行末までの
+///   コメント
+/// * `TokenKind::BlockComment`は`/* ... */`のコメント。複数行にまたがって
+///   もよく、閉じていない場合は入力の最後までとする
+/// * `TokenKind::Punct`は`@`、`(`、`)`、`=`、`;`のどれか1文字
+/// * `TokenKind::Word`はそれ以外の文字の並び（シンボル、モーニック、数値など）
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Bom,
+    Newline,
+    Whitespace,
+    LineComment,
+    BlockComment,
+    Punct,
+    Word,
+}
+
+impl TokenKind {
+    /// 空白、改行、コメントなど、命令の意味に関わらない字句か？
+    pub fn is_trivia(self) -> bool {
+        !matches!(self, TokenKind::Punct | TokenKind::Word)
+    }
+
+    /// コメントか？
+    pub fn is_comment(self) -> bool {
+        matches!(self, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+}
+
+/// 字句。`span`はソース上のバイト単位の範囲で、`text`はその部分の文字列。
+/// `line`と`column`は字句の始まりの行番号と列番号（どちらも1から始まり、
+/// 列は文字単位でBOMを数えない）
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 1文字の字句になる記号
+const PUNCTS: [char; 5] = ['@', '(', ')', '=', ';'];
+
+/// `source`を`comments`の書き方で字句に分ける。`ignore_case`が`true`のときは
+/// `;`の後ろのjumpモーニックの大文字と小文字を区別しない
+pub fn tokenize(source: &str, comments: CommentSyntax, ignore_case: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let (mut line, mut column) = (1, 1);
+
+    if source.starts_with('\u{feff}') {
+        start = '\u{feff}'.len_utf8();
+        tokens.push(Token {
+            kind: TokenKind::Bom,
+            text: source[..start].to_string(),
+            span: 0..start,
+            line,
+            column,
+        });
+    }
+
+    while start < source.len() {
+        let rest = &source[start..];
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        let line_text = rest[..line_end].strip_suffix('\r').unwrap_or(&rest[..line_end]);
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if rest.starts_with('\n') || rest.starts_with("\r\n") {
+            (TokenKind::Newline, if c == '\n' { 1 } else { 2 })
+        } else if rest.starts_with("//") {
+            (TokenKind::LineComment, line_text.len())
+        } else if comments.block && rest.starts_with("/*") {
+            let len = rest[2..].find("*/").map_or(rest.len(), |n| n + 4);
+            (TokenKind::BlockComment, len)
+        } else if comments.semicolon && c == ';' && !is_jump(&line_text[1..], ignore_case) {
+            (TokenKind::LineComment, line_text.len())
+        } else if c.is_whitespace() {
+            let len = rest.char_indices()
+                .find(|(i, c)| {
+                    !c.is_whitespace() || rest[*i..].starts_with('\n')
+                        || rest[*i..].starts_with("\r\n")
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (TokenKind::Whitespace, len)
+        } else if PUNCTS.contains(&c) {
+            (TokenKind::Punct, 1)
+        } else {
+            let len = rest.char_indices()
+                .find(|(i, c)| {
+                    c.is_whitespace() || PUNCTS.contains(c) || rest[*i..].starts_with("//")
+                        || (comments.block && rest[*i..].starts_with("/*"))
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (TokenKind::Word, len)
+        };
+
+        tokens.push(Token {
+            kind,
+            text: rest[..len].to_string(),
+            span: start..start + len,
+            line,
+            column,
+        });
+        for c in rest[..len].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        start += len;
+    }
+
+    tokens
+}
+
+/// ソースの1行分の構文木。`tokens`はこの行で始まる字句で、行末の改行も含む。
+/// 前の行から続くブロックコメントの中の行は字句を持たない
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxLine {
+    pub number: usize,
+    pub tokens: Vec<Token>,
+}
+
+impl SyntaxLine {
+    /// 命令の最初と最後の字句の位置
+    fn code_range(&self) -> Option<(usize, usize)> {
+        let first = self.tokens.iter().position(|t| !t.kind.is_trivia())?;
+        let last = self.tokens.iter().rposition(|t| !t.kind.is_trivia())?;
+        Some((first, last))
+    }
+
+    /// 空白とコメントをすべて取り除いたコマンド（`D=M;JGT`など）を返す。
+    /// コマンドがない行は空文字列を返す
+    pub fn command(&self) -> String {
+        self.tokens.iter()
+            .filter(|t| !t.kind.is_trivia())
+            .map(|t| t.text.as_str())
+            .collect()
+    }
+
+    /// 前後の空白を除き、途中のコメントを空白に置き換えたコマンド（`D = M`など）
+    /// と、その開始列を返す。コマンドがない行は`None`を返す
+    pub fn raw_command(&self) -> Option<(String, usize)> {
+        let (first, last) = self.code_range()?;
+        let raw = self.tokens[first..=last].iter()
+            .map(|t| {
+                if t.kind.is_comment() { " ".repeat(t.text.chars().count()) } else { t.text.clone() }
+            })
+            .collect();
+        Some((raw, self.tokens[first].column))
+    }
+}
+
+/// ソースの構文木。ソースのすべての行を持ち、字句を順につなげると元のソースに戻る
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxTree {
+    pub lines: Vec<SyntaxLine>,
+}
+
+impl SyntaxTree {
+    /// `source`を`comments`の書き方で読み、構文木を作る。`ignore_case`は`tokenize`と同じ
+    pub fn parse(source: &str, comments: CommentSyntax, ignore_case: bool) -> SyntaxTree {
+        let count = source.matches('\n').count() + 1;
+        let mut lines: Vec<SyntaxLine> = (1..=count)
+            .map(|number| SyntaxLine { number, tokens: Vec::new() })
+            .collect();
+
+        for token in tokenize(source, comments, ignore_case) {
+            lines[token.line - 1].tokens.push(token);
+        }
+
+        SyntaxTree { lines }
+    }
+
+    /// すべての字句を順に返す
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        self.lines.iter().flat_map(|l| l.tokens.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tokenize, SyntaxTree, Token, TokenKind};
+    use crate::parser::CommentSyntax;
+
+    fn kinds(tokens: &[Token]) -> Vec<(TokenKind, &str)> {
+        tokens.iter().map(|t| (t.kind, t.text.as_str())).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let source = "\u{feff}  D = M;JGT // test\r\n(LOOP)\n@i /* a\nb */ 0;JMP";
+        let tokens = tokenize(source, CommentSyntax::default(), false);
+        assert_eq!(kinds(&tokens), vec![
+            (TokenKind::Bom, "\u{feff}"),
+            (TokenKind::Whitespace, "  "),
+            (TokenKind::Word, "D"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Punct, "="),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Word, "M"),
+            (TokenKind::Punct, ";"),
+            (TokenKind::Word, "JGT"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::LineComment, "// test"),
+            (TokenKind::Newline, "\r\n"),
+            (TokenKind::Punct, "("),
+            (TokenKind::Word, "LOOP"),
+            (TokenKind::Punct, ")"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::Punct, "@"),
+            (TokenKind::Word, "i"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::BlockComment, "/* a\nb */"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Word, "0"),
+            (TokenKind::Punct, ";"),
+            (TokenKind::Word, "JMP"),
+        ]);
+
+        // 字句の範囲はソースを隙間なく覆う
+        let mut end = 0;
+        for token in &tokens {
+            assert_eq!(token.span.start, end);
+            assert_eq!(&source[token.span.clone()], token.text);
+            end = token.span.end;
+        }
+        assert_eq!(end, source.len());
+    }
+
+    #[test]
+    fn test_tokenize_comment_syntax() {
+        let source = "@R0 ; test\n0 ; JMP\n/* x";
+        let comments = CommentSyntax { block: true, semicolon: true };
+        assert_eq!(kinds(&tokenize(source, comments, false)), vec![
+            (TokenKind::Punct, "@"),
+            (TokenKind::Word, "R0"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::LineComment, "; test"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::Word, "0"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Punct, ";"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Word, "JMP"),
+            (TokenKind::Newline, "\n"),
+            (TokenKind::BlockComment, "/* x"),
+        ]);
+
+        let comments = CommentSyntax { block: false, semicolon: false };
+        assert_eq!(kinds(&tokenize("/* x */@R0//y", comments, false)), vec![
+            (TokenKind::Word, "/*"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Word, "x"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Word, "*/"),
+            (TokenKind::Punct, "@"),
+            (TokenKind::Word, "R0"),
+            (TokenKind::LineComment, "//y"),
+        ]);
+    }
+
+    #[test]
+    fn test_syntax_tree() {
+        let source = "\u{feff}@R0 // a\n/* b\n c */ D = /* d */ M\n\n  goto  LOOP\n";
+        let syntax = SyntaxTree::parse(source, CommentSyntax::default(), false);
+        assert_eq!(syntax.lines.len(), 6);
+        let text: String = syntax.tokens().map(|t| t.text.as_str()).collect();
+        assert_eq!(text, source);
+
+        let commands: Vec<_> = syntax.lines.iter()
+            .map(|l| (l.number, l.command(), l.raw_command()))
+            .collect();
+        assert_eq!(commands, vec![
+            (1, "@R0".to_string(), Some(("@R0".to_string(), 1))),
+            (2, "".to_string(), None),
+            (3, "D=M".to_string(), Some(("D =         M".to_string(), 7))),
+            (4, "".to_string(), None),
+            (5, "gotoLOOP".to_string(), Some(("goto  LOOP".to_string(), 3))),
+            (6, "".to_string(), None),
+        ]);
+    }
+}
@@ -0,0 +1,81 @@
+// シンボルの相互参照表
+
+use crate::assembly::Assembly;
+use crate::symbol_table::SymbolKind;
+
+/// 行番号の一覧を`3, 8`のような文字列にする。空の場合は`-`
+fn lines(lines: &[usize]) -> String {
+    if lines.is_empty() {
+        return "-".to_string()
+    }
+    lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// `assembly`のシンボルの相互参照表を返す。前半は表のすべてのシンボルの種類、
+/// 値、定義した行、参照した行で、後半はラベルが指すROMのアドレスとそのラベル。
+/// 疑似命令の展開で作ったラベルは含めない
+pub fn report(assembly: &Assembly) -> String {
+    let symbols = &assembly.symbols;
+    let visible = || symbols.iter().filter(|s| s.kind != SymbolKind::Internal);
+    let width = visible().map(|s| s.name.len()).max().unwrap_or(0).max("Symbol".len());
+    let mut output = format!("{:width$}  {:10}  {:>5}  {:7}  References\n",
+                             "Symbol", "Kind", "Value", "Defined", width = width);
+
+    for symbol in visible() {
+        let definition = symbol.definition.map_or("-".to_string(), |l| l.to_string());
+        output += &format!("{:width$}  {:10}  {:>5}  {:7}  {}\n",
+                           symbol.name, symbol.kind.name(), symbol.address, definition,
+                           lines(&symbol.references), width = width);
+    }
+
+    let mut addresses: Vec<usize> = symbols.of_kind(SymbolKind::Label)
+        .map(|s| s.address)
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    output += "\nAddress  Labels\n";
+    for address in addresses {
+        let labels: Vec<&str> = symbols.labels_at(address).map(|s| s.name.as_str()).collect();
+        output += &format!("{:>7}  {}\n", address, labels.join(", "));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::report;
+    use crate::assembly::Assembly;
+    use crate::symbol_table::{SymbolKind, SymbolTable};
+
+    #[test]
+    fn test_report() {
+        let mut assembly = Assembly::new("Test.asm");
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("SP", 0, SymbolKind::Predefined, None);
+        symbols.add_label("LOOP", 1, 3).unwrap();
+        symbols.add_label("END", 4, 7).unwrap();
+        symbols.add_label("STOP", 4, 8).unwrap();
+        symbols.add_symbol("counter", 16, SymbolKind::Variable, Some(2));
+        symbols.add_entry("TIMER", 0x6001);
+        symbols.add_symbol("halt 9", 4, SymbolKind::Internal, Some(9));
+        symbols.add_reference("counter", 2);
+        symbols.add_reference("LOOP", 5);
+        symbols.add_reference("counter", 6);
+        assembly.symbols = symbols;
+
+        assert_eq!(report(&assembly), concat!(
+            "Symbol   Kind        Value  Defined  References\n",
+            "SP       predefined      0  -        -\n",
+            "LOOP     label           1  3        5\n",
+            "END      label           4  7        -\n",
+            "STOP     label           4  8        -\n",
+            "counter  variable       16  2        2, 6\n",
+            "TIMER    constant    24577  -        -\n",
+            "\n",
+            "Address  Labels\n",
+            "      1  LOOP\n",
+            "      4  END, STOP\n"));
+    }
+}